@@ -3,42 +3,339 @@
 //    License: GNU GENERAL PUBLIC LICENSE 3
 //        (see LICENSE in the top level directory of the distribution)
 
-use postgres::{Client, NoTls};
-use redis::Commands;
-use uuid::Uuid;
+use json::JsonValue;
+use native_tls::{Certificate, TlsConnector};
+use postgres::config::{Host, SslMode};
+use postgres_native_tls::MakeTlsConnector;
+use redis::{ConnectionAddr, ConnectionInfo, IntoConnectionInfo};
+use url::Url;
 
 use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::thecorpus::*;
+use crate::theerrors::*;
+use crate::themetrics::*;
+use crate::theschema::*;
+use crate::thestorage::*;
 use crate::thestructs::*;
 
-pub fn postgresconnect(j: String) -> postgres::Client {
+// where to look when the JSON says "Host": ""
+static PGSOCKETDIR: &str = "/var/run/postgresql";
+
+pub fn postgresconfig(j: &str) -> Result<(postgres::Config, MakeTlsConnector), String> {
     // https://docs.rs/postgres/0.19.1/postgres/
     // https://rust-lang-nursery.github.io/rust-cookbook/database/postgres.html
-    let parsed = json::parse(&j).unwrap();
-    let host = parsed["Host"].as_str().unwrap();
-    let user = parsed["User"].as_str().unwrap();
-    let db = parsed["DBName"].as_str().unwrap();
-    let port = parsed["Port"].as_i32().unwrap();
-    let pw = parsed["Pass"].as_str().unwrap();
-    let validate = format!("host={} user={} dbname={} port={} password={}", &host, &user, &db, &port, &pw);
-    let client = Client::connect(&validate, NoTls).expect("failed to connect to postgreSQL");
-    client
+    // connections are made by the pool: see DbPools
+    // "j" can be...
+    //  [a] the JSON that HipparchiaServer hands us:
+    //      {"Host": "localhost", "Port": 5432, "User": "hippa_wr", "Pass": "", "DBName": "hipparchiaDB"}
+    //      with optional "SSLMode" and "SSLRootCert"; a "Host" that is empty or starts with "/" means a unix socket
+    //  [b] a libpq connection string: "host=/var/run/postgresql dbname=hipparchiaDB sslmode=verify-full"
+    //  [c] a URL: "postgresql://hippa_wr@localhost:5432/hipparchiaDB?sslmode=require"
+    //
+    // sslmode is one of disable, prefer, require, verify-ca, verify-full; sslrootcert is a PEM CA file
+    // if no password is supplied we look at $PGPASSWORD and then at $PGPASSFILE (or ~/.pgpass)
+
+    let j = j.trim();
+    let mut sslmode: Option<String> = None;
+    let mut sslrootcert: Option<String> = None;
+
+    let mut config: postgres::Config = if j.starts_with('{') {
+        let parsed = json::parse(j).map_err(|e| format!("postgres settings are not valid JSON: {}", e))?;
+        let text = |k: &str| parsed[k].as_str().unwrap_or("").to_string();
+        let mut c = postgres::Config::new();
+        let host = text("Host");
+        c.host(if host.is_empty() { PGSOCKETDIR } else { &host });
+        if let Some(p) = parsed["Port"].as_u16() {
+            c.port(p);
+        }
+        c.user(&text("User"));
+        c.dbname(&text("DBName"));
+        if !text("Pass").is_empty() {
+            c.password(text("Pass"));
+        }
+        // we have always spoken to postgres in the clear: keep doing so unless asked not to
+        sslmode = Some(parsed["SSLMode"].as_str().unwrap_or("disable").to_string());
+        sslrootcert = parsed["SSLRootCert"].as_str().map(|s| s.to_string());
+        c
+    } else if j.starts_with("postgres://") || j.starts_with("postgresql://") {
+        let mut u = Url::parse(j).map_err(|e| format!("cannot parse the postgres URL: {}", e))?;
+        let mut kept: Vec<(String, String)> = Vec::new();
+        for (k, v) in u.query_pairs() {
+            match k.as_ref() {
+                "sslmode" => sslmode = Some(v.to_string()),
+                "sslrootcert" => sslrootcert = Some(v.to_string()),
+                _ => kept.push((k.to_string(), v.to_string())),
+            }
+        }
+        u.set_query(None);
+        if !kept.is_empty() {
+            u.query_pairs_mut().extend_pairs(kept);
+        }
+        u.as_str().parse().map_err(|e| format!("cannot use the postgres URL: {}", e))?
+    } else {
+        let mut kept: Vec<String> = Vec::new();
+        for (k, v) in pg_splitconnstring(j)? {
+            match k.as_str() {
+                "sslmode" => sslmode = Some(v),
+                "sslrootcert" => sslrootcert = Some(v),
+                _ => kept.push(format!("{}='{}'", k, v.replace('\\', "\\\\").replace('\'', "\\'"))),
+            }
+        }
+        kept.join(" ").parse().map_err(|e| format!("cannot use the postgres connection string: {}", e))?
+    };
+
+    if config.get_password().is_none() {
+        if let Some(pw) = pg_lookuppassword(&config) {
+            config.password(pw);
+        }
+    }
+
+    // tokio-postgres only knows disable/prefer/require; verification is up to the TLS connector
+    let mut tlsbuilder = TlsConnector::builder();
+    let mode = sslmode.unwrap_or_else(|| "prefer".to_string());
+    match mode.as_str() {
+        "disable" => { config.ssl_mode(SslMode::Disable); }
+        "prefer" | "require" => {
+            config.ssl_mode(if mode == "prefer" { SslMode::Prefer } else { SslMode::Require });
+            // libpq: encrypt but do not verify unless there is a root certificate to verify against
+            if sslrootcert.is_none() {
+                tlsbuilder.danger_accept_invalid_certs(true);
+            }
+            tlsbuilder.danger_accept_invalid_hostnames(true);
+        }
+        "verify-ca" => {
+            config.ssl_mode(SslMode::Require);
+            tlsbuilder.danger_accept_invalid_hostnames(true);
+        }
+        "verify-full" => { config.ssl_mode(SslMode::Require); }
+        m => return Err(format!("unknown sslmode '{}': use disable, prefer, require, verify-ca or verify-full", m)),
+    }
+
+    if let Some(ca) = sslrootcert {
+        let pem = fs::read(&ca).map_err(|e| format!("cannot read sslrootcert {}: {}", ca, e))?;
+        let cert = Certificate::from_pem(&pem).map_err(|e| format!("bad sslrootcert {}: {}", ca, e))?;
+        tlsbuilder.add_root_certificate(cert);
+    }
+
+    let connector = tlsbuilder.build().map_err(|e| format!("cannot set up TLS for postgres: {}", e))?;
+    Ok((config, MakeTlsConnector::new(connector)))
+}
+
+pub fn pg_describe(c: &postgres::Config) -> String {
+    // "hippa_wr@localhost:5432/hipparchiaDB" for the error messages; never the password
+    let host = match c.get_hosts().first() {
+        Some(Host::Tcp(h)) => h.clone(),
+        Some(Host::Unix(p)) => p.display().to_string(),
+        None => "".to_string(),
+    };
+    let port = c.get_ports().first().copied().unwrap_or(5432);
+    format!("{}@{}:{}/{}", c.get_user().unwrap_or(""), host, port, c.get_dbname().unwrap_or(""))
+}
+
+fn pg_splitconnstring(s: &str) -> Result<Vec<(String, String)>, String> {
+    // "host=localhost password='it\'s a secret'" -> [("host", "localhost"), ("password", "it's a secret")]
+    // (libpq quoting: values may be single-quoted; backslash escapes the next character, so a quote inside a quoted
+    // value is \' and not '' as in SQL)
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.peek().copied() {
+            if c == '=' || c.is_whitespace() {
+                break
+            }
+            key.push(c);
+            chars.next();
+        }
+        if key.is_empty() {
+            return Err("missing key in the postgres connection string".to_string())
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.next() != Some('=') {
+            return Err(format!("missing \"=\" after \"{}\" in the postgres connection string", key))
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut val = String::new();
+        if chars.peek() == Some(&'\'') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('\\') => val.extend(chars.next()),
+                    Some('\'') => break,
+                    Some(c) => val.push(c),
+                    None => return Err("unterminated quote in the postgres connection string".to_string()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek().copied() {
+                if c.is_whitespace() {
+                    break
+                }
+                chars.next();
+                if c == '\\' {
+                    val.extend(chars.next());
+                } else {
+                    val.push(c);
+                }
+            }
+        }
+        pairs.push((key, val));
+    }
+    Ok(pairs)
 }
 
-pub fn redisconnect(j: String) -> redis::Connection {
+fn pg_lookuppassword(c: &postgres::Config) -> Option<String> {
+    // $PGPASSWORD, else the first matching line of $PGPASSFILE or ~/.pgpass
+    //  hostname:port:database:username:password (with "*" as a wildcard; a unix socket counts as "localhost")
+    if let Ok(pw) = env::var("PGPASSWORD") {
+        return Some(pw)
+    }
+
+    let passfile: PathBuf = match env::var("PGPASSFILE") {
+        Ok(p) => PathBuf::from(p),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".pgpass"),
+    };
+    let contents = fs::read_to_string(passfile).ok()?;
+
+    let host = match c.get_hosts().first() {
+        Some(Host::Tcp(h)) => h.clone(),
+        _ => "localhost".to_string(),
+    };
+    let port = c.get_ports().first().copied().unwrap_or(5432).to_string();
+    let user = c.get_user().unwrap_or("").to_string();
+    let db = c.get_dbname().unwrap_or(&user).to_string();
+    let wanted = [host, port, db, user];
+
+    for line in contents.lines() {
+        if line.starts_with('#') {
+            continue
+        }
+        let fields = pg_splitpassline(line);
+        if fields.len() != 5 {
+            continue
+        }
+        if fields.iter().zip(wanted.iter()).all(|(f, w)| f == "*" || f == w) {
+            return Some(fields[4].clone())
+        }
+    }
+    None
+}
+
+fn pg_splitpassline(line: &str) -> Vec<String> {
+    // split on ":" but honour "\:" and "\\"
+    let mut fields: Vec<String> = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            ':' => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+pub fn rs_describe(info: &ConnectionInfo) -> String {
+    // "redis://localhost:6379/0" for the error messages; never the password
+    match &*info.addr {
+        ConnectionAddr::Tcp(h, p) => format!("redis://{}:{}/{}", h, p, info.db),
+        ConnectionAddr::TcpTls { host, port, .. } => format!("rediss://{}:{}/{}", host, port, info.db),
+        ConnectionAddr::Unix(path) => format!("unix://{}?db={}", path.display(), info.db),
+    }
+}
+
+pub fn redisconninfo(j: &str) -> Result<ConnectionInfo, String> {
     // https://medium.com/swlh/tutorial-getting-started-with-rust-and-redis-69041dd38279
-    let parsed = json::parse(&j).unwrap();
-    let redis_host_name = parsed["Addr"].as_str().unwrap();
-    let redis_password = parsed["Password"].as_str().unwrap();
-    // let redis_db = parsed["DB"].as_str().unwrap();
-    let uri_scheme = "redis";
-    let redis_conn_url = format!("{}://:{}@{}", uri_scheme, redis_password, redis_host_name);
-    redis::Client::open(redis_conn_url)
-        .expect("Invalid connection URL")
-        .get_connection()
-        .expect("failed to connect to Redis")
+    // either a URL:
+    //      redis://[user][:password@]host[:port][/db]
+    //      rediss://...                                    (TLS)
+    //      unix:///path/to/redis.sock?db=N&user=U&pass=P
+    // or the JSON that HipparchiaServer hands us:
+    //      {"Addr": "localhost:6379", "Password": "", "DB": 0}
+    //  with optional "User" for ACL auth and "TLS": true; an "Addr" that starts with "/" is a unix socket, and an IPv6
+    //  address goes in brackets: "[::1]:6379"
+    let j = j.trim();
+    if ["redis://", "rediss://", "unix://", "redis+unix://"].iter().any(|s| j.starts_with(s)) {
+        // redis hands back an IPv6 host as "[::1]", which does not resolve: take the brackets off
+        let mut info = j.into_connection_info().map_err(|e| format!("cannot use the redis URL: {}", e))?;
+        match info.addr.as_mut() {
+            ConnectionAddr::Tcp(host, _) | ConnectionAddr::TcpTls { host, .. } if host.starts_with('[') => {
+                *host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+            }
+            _ => (),
+        }
+        return Ok(info)
+    }
+
+    let parsed = json::parse(j).map_err(|e| format!("redis settings are neither a URL nor valid JSON: {}", e))?;
+    let addr = parsed["Addr"].as_str().unwrap_or("localhost:6379");
+    let nonempty = |k: &str| parsed[k].as_str().filter(|v| !v.is_empty()).map(|v| v.to_string());
+
+    // "DB": 0 and "DB": "0" are both out there
+    let db: i64 = match &parsed["DB"] {
+        JsonValue::Null => 0,
+        d => d.as_i64()
+            .or_else(|| d.as_str().and_then(|s| s.trim().parse().ok()))
+            .ok_or_else(|| format!("redis \"DB\" must be a number, not {}", d.dump()))?,
+    };
+
+    let address: ConnectionAddr = if addr.starts_with('/') {
+        ConnectionAddr::Unix(PathBuf::from(addr))
+    } else {
+        let (host, port) = rs_splitaddr(addr)?;
+        if parsed["TLS"].as_bool().unwrap_or(false) {
+            ConnectionAddr::TcpTls { host: host.to_string(), port, insecure: false }
+        } else {
+            ConnectionAddr::Tcp(host.to_string(), port)
+        }
+    };
+
+    Ok(ConnectionInfo {
+        addr: Box::new(address),
+        db,
+        username: nonempty("User"),
+        passwd: nonempty("Password"),
+    })
 }
 
-pub fn db_sv_get_morphobjects(words: &mut Vec<&str>, lang: &str, pg: &mut postgres::Client) -> Vec<DbMorphology> {
+fn rs_splitaddr(addr: &str) -> Result<(&str, u16), String> {
+    // "localhost:6379" -> ("localhost", 6379); "[::1]:6379" -> ("::1", 6379); no port is 6379
+    let port = |p: &str| p.parse::<u16>().map_err(|_| format!("bad port in redis \"Addr\": {}", addr));
+    if let Some(bracketed) = addr.strip_prefix('[') {
+        return match bracketed.split_once(']') {
+            Some((h, "")) => Ok((h, 6379)),
+            Some((h, p)) => match p.strip_prefix(':') {
+                Some(p) => Ok((h, port(p)?)),
+                None => Err(format!("bad redis \"Addr\": {}", addr)),
+            },
+            None => Err(format!("unclosed '[' in redis \"Addr\": {}", addr)),
+        }
+    }
+    if addr.matches(':').count() > 1 {
+        // "::1" is not host ":" and port 1
+        return Err(format!("an IPv6 address in redis \"Addr\" needs brackets, e.g. [::1]:6379, not {}", addr))
+    }
+    match addr.rsplit_once(':') {
+        Some((h, p)) => Ok((h, port(p)?)),
+        None => Ok((addr, 6379)),
+    }
+}
+
+pub fn db_sv_get_morphobjects(words: &mut Vec<&str>, lang: &str, db: &mut dyn CorpusConn) -> HelperResult<Vec<DbMorphology>> {
     // the worker for sv_getrequiredmorphobjects()
     // look for the upper case matches too: Ϲωκράτηϲ and not just ϲωκρατέω (!)
     // let start = Instant::now();
@@ -47,17 +344,13 @@ pub fn db_sv_get_morphobjects(words: &mut Vec<&str>, lang: &str, pg: &mut postgr
     let mut w = words.iter().map(|w| w.to_string()).collect();
     wordswithcaps.append(&mut w);
 
-    let mut rndid = Uuid::new_v4().to_string();
-    rndid.retain(|c| c != '-');
-
-    let ttarr = wordswithcaps.join("', '");
-    let ttarr = format!("'{}'", ttarr);
-    let t = format!("CREATE TEMPORARY TABLE ttw_{} AS SELECT words AS w FROM unnest(ARRAY[{}]) words", &rndid, ttarr);
-
-    pg.execute(t.as_str(), &[]).ok().expect("db_arraytogetrequiredmorphobjects() TempTable creation failed");
+    db.morphology(&wordswithcaps, lang)
+}
 
-    let q = format!("SELECT observed_form, xrefs, prefixrefs, related_headwords FROM {}_morphology WHERE EXISTS (SELECT 1 FROM ttw_{} temptable WHERE temptable.w = {}_morphology.observed_form)", &lang, rndid, &lang);
-    let dbmo = pg.query(q.as_str(), &[]).unwrap().into_iter()
+pub fn db_morphology(words: &[String], lang: &str, pg: &mut postgres::Client) -> HelperResult<Vec<DbMorphology>> {
+    // the words travel as a single text[] parameter: no quoting problems with "d’" or "M.’" and no temp table to leak
+    let q = format!("SELECT observed_form, xrefs, prefixrefs, related_headwords FROM {}_morphology WHERE observed_form = ANY($1)", &lang);
+    let dbmo = pg.query(q.as_str(), &[&words])?.into_iter()
         .map(|row| DbMorphology {
             obs: row.get("observed_form"),
             xrf: row.get("xrefs"),
@@ -66,7 +359,7 @@ pub fn db_sv_get_morphobjects(words: &mut Vec<&str>, lang: &str, pg: &mut postgr
             upo: row.get::<&str, String>("related_headwords").split_whitespace().map(|s| s.to_string()).collect(),
         }).collect::<Vec<DbMorphology>>();
 
-    dbmo
+    Ok(dbmo)
 }
 
 pub fn db_fields<'a>() ->  Vec<&'a str> {
@@ -76,100 +369,91 @@ pub fn db_fields<'a>() ->  Vec<&'a str> {
     v
 }
 
-pub fn db_directfetch(t: &str, s: i32, e: i32, pg: &mut postgres::Client) -> Vec<DBLine> {
+pub fn db_linejson(l: &DBLine) -> JsonValue {
+    // {"WkUID": "lt0448w001", "TbIndex": 1, "Lvl5Value": "-1", ...}: a hit as HipparchiaServer reads it
+    let values = [&l.uid, &l.l5, &l.l4, &l.l3, &l.l2, &l.l1, &l.l0, &l.mu, &l.ac, &l.st, &l.hy, &l.an];
+    let flds = db_fields();
+    let mut data = JsonValue::new_object();
+    data[flds[0]] = values[0].as_str().into();
+    data[flds[1]] = l.idx.into();
+    for (f, v) in flds[2..].iter().zip(&values[1..]) {
+        data[*f] = v.as_str().into();
+    }
+    data
+}
+
+pub fn db_directfetch(t: &str, s: i32, e: i32, pg: &mut postgres::Client) -> HelperResult<Vec<DBLine>> {
     // let q = "SELECT * FROM lt0448 WHERE index BETWEEN 1 and 25";
     let q = format!("SELECT * FROM {} WHERE index BETWEEN {} and {}", t, s, e);
-    let lines: Vec<DBLine> = pg.query(q.as_str(), &[]).unwrap().into_iter()
-        .map(|row| DBLine {
-            idx: row.get("index"),
-            uid: row.get("wkuniversalid"),
-            l5: row.get("level_05_value"),
-            l4: row.get("level_04_value"),
-            l3: row.get("level_03_value"),
-            l2: row.get("level_02_value"),
-            l1: row.get("level_01_value"),
-            l0: row.get("level_00_value"),
-            mu: row.get("marked_up_line"),
-            ac: row.get("accented_line"),
-            st: row.get("stripped_line"),
-            hy: row.get("hyphenated_words"),
-            an: row.get("annotations"),
-        }).collect::<Vec<DBLine>>();
-    lines
+    let rows = pg.query(q.as_str(), &[])?;
+    mt_count(MtCounter::Queries);
+    mt_add(MtCounter::RowsScanned, rows.len() as u64);
+    let lines: Vec<DBLine> = rows.into_iter()
+        .map(|row| sc_dbline(&row)).collect::<Vec<DBLine>>();
+    Ok(lines)
 }
 
-pub fn db_redisfectch(thekey: &str, pg: &str, rc: &str) -> Vec<DBLine> {
-    let mut redisconn = redisconnect(rc.to_string());
-    let mut psqlclient = postgresconnect(pg.to_string());
-
+pub fn db_redisfectch(thekey: &str, db: &mut dyn CorpusConn, redisconn: &mut RsConn) -> HelperResult<Vec<DBLine>> {
     let mut foundlines: Vec<DBLine> = Vec::new();
-    loop {
-        // [a] pop a query stored as json in redis
-        let j = rs_spop(&thekey, &mut redisconn);
-        if &j == &"" { break }
+    // [a] pop a query stored as json in redis
+    while let Some(j) = rs_spop(thekey, redisconn)? {
         // [b] update the polling data
-        let workpile = rs_scard(&thekey, &mut redisconn);
+        let workpile = rs_scard(thekey, redisconn)?;
         let w = workpile.to_string();
         let thiskey = format!("{}_remaining", &thekey);
-        rs_set_str(&thiskey, w.as_str(), &mut redisconn).unwrap();
+        rs_set_str(&thiskey, w.as_str(), redisconn)?;
 
         // [c] decode the query
-        let parsed = json::parse(j.as_str()).unwrap();
-        let t = parsed["TempTable"].as_str().unwrap();
-        let q = parsed["PsqlQuery"].as_str().unwrap();
-        let _d = parsed["PsqlData"].as_str().unwrap();  // never any data, right...?
-
-        // [d] build a temp table if needed
-        if &t != &"" {
-            psqlclient.execute(t, &[]).ok().expect("TempTable creation failed");
-        }
+        //  HipparchiaServer never sends any data here, but the same work items that grabworker() takes should work
+        let (t, q, d) = db_workitem(&j)?;
 
-        // [e] execute the main query && aggregate the finds
-        // https://siciarz.net/24-days-of-rust-postgres/
-        // https://docs.rs/postgres/0.19.1/postgres/index.html
-        let thelines: Vec<DBLine> = psqlclient.query(q, &[]).unwrap().into_iter()
-            .map(|row| DBLine {
-                idx: row.get("index"),
-                uid: row.get("wkuniversalid"),
-                l5: row.get("level_05_value"),
-                l4: row.get("level_04_value"),
-                l3: row.get("level_03_value"),
-                l2: row.get("level_02_value"),
-                l1: row.get("level_01_value"),
-                l0: row.get("level_00_value"),
-                mu: row.get("marked_up_line"),
-                ac: row.get("accented_line"),
-                st: row.get("stripped_line"),
-                hy: row.get("hyphenated_words"),
-                an: row.get("annotations"),
-            }).collect::<Vec<DBLine>>();
-        foundlines.extend(thelines);
+        // [d] run it && aggregate the finds
+        foundlines.extend(db.workitem(&t, &q, &d)?);
     }
     // println!("db_redisfectch found {} lines", &foundlines.len());
-    foundlines
+    Ok(foundlines)
 }
 
-pub fn db_fetchheadwordcounts(hw: Vec<String>, pg: &mut postgres::Client) -> HashMap<String, i32> {
-
-    let mut rndid = Uuid::new_v4().to_string();
-    rndid.retain(|c| c != '-');
-
-    let ttarr = format!("'{}'", hw.join("', '"));
-    let t = format!("CREATE TEMPORARY TABLE ttw_{} AS SELECT words AS w FROM unnest(ARRAY[{}]) words", &rndid, ttarr);
-    pg.execute(t.as_str(), &[]).ok().expect("db_fetchheadwordcounts() TempTable creation failed");
+pub fn db_workitemlines(t: &str, q: &str, d: &str, pg: &mut postgres::Client) -> HelperResult<Vec<DBLine>> {
+    // [a] build a temp table if needed
+    if !t.is_empty() {
+        pg.execute(t, &[])?;
+    }
 
-    let q = format!("SELECT entry_name, total_count FROM dictionary_headword_wordcounts WHERE EXISTS (SELECT 1 FROM ttw_{} temptable WHERE temptable.w = dictionary_headword_wordcounts.entry_name)", rndid);
+    // [b] execute the main query: HipparchiaServer's searches always have a $1, but a vectors item may not
+    //  postgres says how many parameters there are: a "$1" inside a regex or a string literal is not one
+    // https://siciarz.net/24-days-of-rust-postgres/
+    // https://docs.rs/postgres/0.19.1/postgres/index.html
+    let stmt = pg.prepare(q)?;
+    let rows = match stmt.params().len() {
+        0 => pg.query(&stmt, &[])?,
+        1 => pg.query(&stmt, &[&d])?,
+        n => return Err(HelperError::WorkItem(format!("a work item has one PsqlData, but its query wants {} parameters: {}", n, q))),
+    };
+    mt_count(MtCounter::Queries);
+    mt_add(MtCounter::RowsScanned, rows.len() as u64);
+    let lines: Vec<DBLine> = rows.into_iter()
+        .map(|row| sc_dbline(&row)).collect::<Vec<DBLine>>();
+    Ok(lines)
+}
 
-    let whwvec: Vec<WeightedHeadword> = pg.query(q.as_str(), &[]).unwrap().into_iter()
-        .map(|row| WeightedHeadword {
-            wd: row.get("entry_name"),
-            ct: row.get("total_count"),
-        })
-        .collect::<Vec<WeightedHeadword>>();
+pub fn db_workitem(j: &str) -> HelperResult<(String, String, String)> {
+    // {"TempTable": "", "PsqlQuery": "SELECT ...", "PsqlData": "..."} -> (TempTable, PsqlQuery, PsqlData)
+    let parsed = json::parse(j).map_err(|e| HelperError::WorkItem(format!("not JSON ({}): {}", e, j)))?;
+    let mut fields: Vec<String> = Vec::new();
+    for f in ["TempTable", "PsqlQuery", "PsqlData"] {
+        match parsed[f].as_str() {
+            Some(v) => fields.push(v.to_string()),
+            None => return Err(HelperError::WorkItem(format!("no {} in {}", f, j))),
+        }
+    }
+    Ok((fields[0].clone(), fields[1].clone(), fields[2].clone()))
+}
 
+pub fn db_fetchheadwordcounts(hw: Vec<String>, db: &mut dyn CorpusConn) -> HelperResult<HashMap<String, i32>> {
     let mut wtwhhm: HashMap<String, i32> = HashMap::new();
 
-    for w in whwvec {
+    for w in db.headwordcounts(&hw)? {
         wtwhhm.insert(w.wd, w.ct);
     }
 
@@ -181,67 +465,20 @@ pub fn db_fetchheadwordcounts(hw: Vec<String>, pg: &mut postgres::Client) -> Has
             wtwhhm.insert(k.clone(), 0);
         }
     };
-    wtwhhm
-}
-
-pub fn rs_del(k: &str, c: &mut redis::Connection) -> redis::RedisResult<()> {
-    // DEL
-    let _ : () = c.del(k)?;
-    Ok(())
-}
-
-pub fn rs_scard(k: &str, c: &mut redis::Connection) -> i32 {
-    // SCARD
-
-    // https://stackoverflow.com/questions/51141672/how-do-i-ignore-an-error-returned-from-a-rust-function-and-proceed-regardless
-    // https://doc.rust-lang.org/book/ch06-02-match.html
-
-    let r: Option<i32> = c.scard(k).ok();
-    let count = match r {
-        None => 0,
-        Some(n) => n,
-    };
-    count
-}
-
-pub fn rs_spop(k: &str, c: &mut redis::Connection) -> String {
-    // SPOP
-    let p: String = match redis::cmd("SPOP")
-        .arg(k)
-        .query(c) {
-        Ok(s) => s,
-        Err(_e) => "".to_string(),
-    };
-    p
-}
-
-pub fn rs_get(k: &str, c: &mut redis::Connection) -> String {
-    // GET
-    let p: String = match redis::cmd("GET")
-        .arg(k)
-        .query(c) {
-        Ok(s) => s,
-        Err(_e) => "".to_string(),
-    };
-    p
-}
-
-pub fn rs_sadd(k: &str, v: &str, c: &mut redis::Connection) -> redis::RedisResult<()> {
-    // SADD
-    let _ : () = c.sadd(k, v).unwrap_or(());
-    Ok(())
+    Ok(wtwhhm)
 }
 
-pub fn rs_set_str(k: &str, v: &str, c: &mut redis::Connection) -> redis::RedisResult<()> {
-    // SET
-    let _ : () = c.set(k, v).unwrap_or(());
-    Ok(())
-}
+pub fn db_headwordcounts(hw: &[String], pg: &mut postgres::Client) -> HelperResult<Vec<WeightedHeadword>> {
+    // see db_morphology() on binding the list as text[]
+    let q = "SELECT entry_name, total_count FROM dictionary_headword_wordcounts WHERE entry_name = ANY($1)";
 
-pub fn rs_set_int(k: &str, v: i32, c: &mut redis::Connection) -> redis::RedisResult<()> {
-    // SET
-    let _ : () = c.set(k, v).unwrap_or(());
-    Ok(())
+    let whwvec: Vec<WeightedHeadword> = pg.query(q, &[&hw])?.into_iter()
+        .map(|row| WeightedHeadword {
+            wd: row.get("entry_name"),
+            ct: row.get("total_count"),
+        })
+        .collect::<Vec<WeightedHeadword>>();
+    Ok(whwvec)
 }
 
 pub fn str_cap(s: &str) -> String {
@@ -256,3 +493,82 @@ pub fn str_lcs(s: &str) -> String {
             s.chars().skip(1).collect::<String>())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redis(j: &str) -> (ConnectionAddr, i64, Option<String>, Option<String>) {
+        let c = redisconninfo(j).unwrap();
+        (*c.addr, c.db, c.username, c.passwd)
+    }
+
+    fn tcp(host: &str, port: u16) -> ConnectionAddr {
+        ConnectionAddr::Tcp(host.to_string(), port)
+    }
+
+    #[test]
+    fn redis_json() {
+        assert_eq!(redis(r#"{"Addr": "localhost:6379", "Password": "", "DB": 0}"#), (tcp("localhost", 6379), 0, None, None));
+        assert_eq!(redis(r#"{"Addr": "redis.example.org", "Password": "pw", "DB": "2", "User": "hippa"}"#),
+                   (tcp("redis.example.org", 6379), 2, Some("hippa".to_string()), Some("pw".to_string())));
+        assert_eq!(redis(r#"{"Addr": "redis.example.org:6380", "TLS": true}"#).0,
+                   ConnectionAddr::TcpTls { host: "redis.example.org".to_string(), port: 6380, insecure: false });
+        assert_eq!(redis(r#"{"Addr": "/run/redis/redis.sock", "DB": 1}"#).0, ConnectionAddr::Unix(PathBuf::from("/run/redis/redis.sock")));
+        assert_eq!(redis("{}").0, tcp("localhost", 6379));
+        assert!(redisconninfo(r#"{"DB": "one"}"#).is_err());
+        assert!(redisconninfo(r#"{"Addr": "localhost:port"}"#).is_err());
+        assert!(redisconninfo("localhost:6379").is_err());
+    }
+
+    #[test]
+    fn redis_ipv6() {
+        assert_eq!(redis(r#"{"Addr": "[::1]:6380"}"#).0, tcp("::1", 6380));
+        assert_eq!(redis(r#"{"Addr": "[fe80::1]"}"#).0, tcp("fe80::1", 6379));
+        assert!(redisconninfo(r#"{"Addr": "::1"}"#).is_err());
+        assert!(redisconninfo(r#"{"Addr": "[::1"}"#).is_err());
+        assert!(redisconninfo(r#"{"Addr": "[::1]6379"}"#).is_err());
+    }
+
+    #[test]
+    fn redis_urls() {
+        assert_eq!(redis("redis://hippa:pw@redis.example.org:6380/3"),
+                   (tcp("redis.example.org", 6380), 3, Some("hippa".to_string()), Some("pw".to_string())));
+        assert_eq!(redis("rediss://redis.example.org").0,
+                   ConnectionAddr::TcpTls { host: "redis.example.org".to_string(), port: 6379, insecure: false });
+        assert_eq!(redis("unix:///run/redis/redis.sock?db=4").0, ConnectionAddr::Unix(PathBuf::from("/run/redis/redis.sock")));
+        assert_eq!(redis("redis://[::1]:6380").0, tcp("::1", 6380));
+    }
+
+    fn split(s: &str) -> Vec<(String, String)> {
+        pg_splitconnstring(s).unwrap()
+    }
+
+    fn pairs(p: &[(&str, &str)]) -> Vec<(String, String)> {
+        p.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn connstring_quoting() {
+        assert_eq!(split("host=localhost password='it\\'s a secret'"), pairs(&[("host", "localhost"), ("password", "it's a secret")]));
+        assert_eq!(split("  dbname = hipparchiaDB   user=hippa_wr "), pairs(&[("dbname", "hipparchiaDB"), ("user", "hippa_wr")]));
+        assert_eq!(split("password='' host=/tmp"), pairs(&[("password", ""), ("host", "/tmp")]));
+        assert_eq!(split("password='a \\\\ b'"), pairs(&[("password", "a \\ b")]));
+        assert_eq!(split("password=a\\ b"), pairs(&[("password", "a b")]));
+        assert_eq!(split(""), pairs(&[]));
+
+        // and the values survive being quoted again for the postgres crate
+        let (c, _) = postgresconfig("host=/tmp user=hippa_wr password='it\\'s a \\\\secret' sslmode=disable").unwrap();
+        assert_eq!(c.get_password(), Some("it's a \\secret".as_bytes()));
+        assert_eq!(pg_describe(&c), "hippa_wr@/tmp:5432/");
+    }
+
+    #[test]
+    fn connstring_errors() {
+        // SQL's '' is not libpq's: the second quote ends the value and what follows has no "="
+        assert!(pg_splitconnstring("password='it''s a secret'").is_err());
+        assert!(pg_splitconnstring("=localhost").is_err());
+        assert!(pg_splitconnstring("host").is_err());
+        assert!(pg_splitconnstring("host localhost").is_err());
+        assert!(pg_splitconnstring("password='open").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// the helper as a library: HipparchiaServer runs the CLI (main.rs), but another Rust program can link this
+// and call the same code directly
+//
+//  searches:    grabber() runs the work items under a redis key with a pool of workers
+//  vectors:     BagPipeline is vector_prep() as a list of stages ([A] fetch ... [J] store), any of which can
+//               be replaced, removed, or have a stage of your own put after it
+//  morphology:  sv_lookupmorphology() maps words to their possible headwords
+//  corpora:     the bagging code reads postgres, or CSV/JSONL dumps of the same tables (FileCorpus)
+//  compat:      --compat go writes what HipparchiaGoDBHelper writes; cp_compare() diffs two sets of results
+//  bench:       bench() times the vector stages and a set of reference searches, as in NOTES.txt
+//  services:    the websocket, serve and http modes and the config/logging/metrics plumbing that the CLI uses
+//  python:      with --features python the same library is also an extension module (see thepython.rs)
+//
+// everything else stays inside the crate and may change without notice
+
+mod dbfunctions;
+mod helpers;
+mod svfunctions;
+mod thebench;
+mod thecompat;
+mod theconfig;
+mod thecorpus;
+mod thedaemon;
+mod theerrors;
+mod thefiles;
+mod thegrabber;
+mod thehttp;
+mod thelogging;
+mod themetrics;
+mod thepools;
+#[cfg(feature = "python")]
+mod thepython;
+mod theredis;
+mod theschema;
+mod thestorage;
+mod thestructs;
+mod thevectors;
+mod thewebsockets;
+
+// searches
+pub use crate::dbfunctions::db_workitem;
+pub use crate::thegrabber::grabber;
+
+// vectors
+pub use crate::svfunctions::{sv_acuteforgrave, sv_buildcompositebags, sv_buildflatbags, sv_buildsentences,
+                             sv_buildwinnertakesallbags, sv_dropstopwords, sv_findallwords, sv_stripper, sv_swapper};
+pub use crate::thevectors::{vector_prep, BagBuild, BagClean, BagContext, BagFetch, BagMorphmap, BagMorphology,
+                            BagPipeline, BagSentences, BagStage, BagState, BagStopwords, BagStore, BagUnify, BagWords};
+
+// morphology
+pub use crate::svfunctions::{sv_buildmorphmap, sv_getrequiredmorphobjects, sv_lookupmorphology};
+pub use crate::thestructs::{DBLine, DbMorphology, WeightedHeadword};
+
+// connections and errors
+pub use crate::theerrors::{er_catchpanic, er_panicreports, er_report, HelperError, HelperResult};
+pub use crate::thepools::{poolsize, servepoolsize, DbPools, MEMORYSTORE, PGDEFAULT, RSDEFAULT};
+
+// storage: redis, or everything in this process
+pub use crate::theredis::RedisStore;
+pub use crate::thestorage::{rs_del, rs_exists, rs_get, rs_rpush, rs_sadd, rs_saddmany, rs_scard, rs_set_int, rs_set_str,
+                            rs_smembers, rs_spop, HelperStore, MemoryStore, RsConn, RsError, RsResult, StoreConn};
+
+// the database, or a corpus exported from it
+pub use crate::thecorpus::{CorpusConn, CorpusGuard, CorpusSource, PgCorpus};
+pub use crate::thefiles::FileCorpus;
+pub use crate::theschema::{sc_detect, DbSchema};
+
+// output compatibility with HipparchiaGoDBHelper
+pub use crate::thecompat::{cp_compare, cp_dump, cp_gojson, cp_readfile, cp_sortedobject, HelperFormat};
+
+// timing runs
+pub use crate::thebench::{bench, BenchSettings};
+
+// services
+pub use crate::theconfig::{HelperConfig, HelperMode};
+pub use crate::thedaemon::{daemon, dm_execute, dm_validate, DmJob};
+pub use crate::thehttp::httpapi;
+pub use crate::thelogging::{lg_clearkey, lg_init, lg_setkey};
+pub use crate::themetrics::{mt_render, mt_serve, MtDump};
+pub use crate::thewebsockets::{websocket, ws_pollsnapshot, WsGatekeeper, WsSettings};
@@ -3,22 +3,17 @@
 //    License: GNU GENERAL PUBLIC LICENSE 3
 //        (see LICENSE in the top level directory of the distribution)
 
-use std::process;
+use std::sync::Arc;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+use log::{debug, info};
 
-use crate::helpers::*;
-use crate::thegrabber::*;
-use crate::thevectors::*;
-use crate::thewebsockets::*;
+use hipparchia_rust_dbhelper::{bench, cp_compare, cp_readfile, daemon, er_panicreports, grabber, httpapi, lg_init, mt_serve,
+                               poolsize, rs_smembers, servepoolsize, vector_prep, websocket, DbPools, FileCorpus,
+                               HelperConfig, HelperError, HelperMode, HelperResult, MtDump, RsConn, WsGatekeeper,
+                               WsSettings, BenchSettings, PGDEFAULT, RSDEFAULT};
 
-mod dbfunctions;
-mod helpers;
-mod svfunctions;
-mod thestructs;
-mod thewebsockets;
-mod thevectors;
-mod thegrabber;
+// the CLI that HipparchiaServer runs: everything that does the work is in lib.rs
 
 static MYNAME: &str = "Hipparchia Rust Helper";
 static VERSION: &str = "0.1.9";
@@ -28,186 +23,635 @@ static TESTEND: &str = "26";
 static TESTKEY: &str = "rusttest";
 static WORKERSDEFAULT: &str = "5";
 static HITSDEFAULT: &str = "200";
-static PSQ: &str = r#"{"Host": "localhost", "Port": 5432, "User": "hippa_wr", "Pass": "", "DBName": "hipparchiaDB"}"#;
-static RP: &str = r#"{"Addr": "localhost:6379", "Password": "", "DB": 0}"#;
+static QUEUEDEFAULT: &str = "hrh_jobs";
+static RUNSDEFAULT: &str = "5";
 
-// Hipparchia Rust Helper CLI Debugging Interface (v.0.1.5)
-// Hipparchia Rust Helper 0.1.5
+// Hipparchia Rust Helper 0.1.9
 //
 // USAGE:
-//     HipparchiaRustDBHelper [FLAGS] [OPTIONS]
+//     hipparchia_rust_dbhelper [OPTIONS] [SUBCOMMAND]
 //
 // FLAGS:
 //     -h, --help       Prints help information
-//         --sv         [vectors] assert that this is a vectorizing run
-//         --v          [common] print version and exit
 //     -V, --version    Prints version information
-//         --ws         [websockets] assert that you are requesting the websocket server
 //
 // OPTIONS:
-//         --c <c>          [searches] max hit count [default: 200]
-//         --k <k>          [searches] redis key to use [default: rusttest]
-//         --l <l>          [common] logging level [default: 0]
-//         --p <p>          [common] postgres login info (as JSON) [default: {"Host": "localhost", "Port": 5432, "User":
-//                          "hippa_wr", "Pass": "", "DBName": "hipparchiaDB"}]
-//         --r <r>          [common] redis login info (as JSON) [default: {"Addr": "localhost:6379", "Password": "", "DB":
-//                          0}]
-//         --svb <svb>      [vectors] the bagging method: choices are alternates, flat, unlemmatized, winnertakesall
-//                          [default: winnertakesall]
-//         --svbs <svbs>    [vectors] number of sentences per bag [default: 1]
-//         --svdb <svdb>    [vectors][for manual debugging] db to grab from [default: lt0448]
-//         --sve <sve>      [vectors][for manual debugging] last line to grab [default: 26]
-//         --svs <svs>      [vectors][for manual debugging] first line to grab [default: 1]
-//         --t <t>          [common] number of workers to dispatch [default: 5]
-//         --wsf <wsf>      [websockets] fail threshold before messages stop being sent [default: 4]
-//         --wsh <wsh>      [websockets] IP address to open up [default: 127.0.0.1]
-//         --wsp <wsp>      [websockets] port on which to open the websocket server [default: 5010]
-//         --wss <wss>      [websockets] save the polls instead of deleting them: 0 is no; 1 is yes [default: 0]
+//         --compat <compat>    [common] write hits, bags and progress messages as this helper does ('rust') or as
+//                              HipparchiaGoDBHelper does ('go') [default: rust]
+//         --config <config>    [common] TOML file of settings: overridden by $HRH_* and then by flags on the command line
+//         --l <l>              [common] logging level: 0 is warnings and errors, 1 info, 2 debug, 3 trace [default: 0]
+//         --logfile <logfile>      [common] append the log to this file instead of writing it to stderr
+//         --logformat <logformat>  [common] log line format [default: text]  [possible values: text, json]
+//         --mf <mf>            [common] write the metrics (Prometheus text format) to this file when the run ends
+//         --mh <mh>            [common] IP address for the metrics listener [default: 127.0.0.1]
+//         --mp <mp>            [common] serve the metrics at http://--mh:--mp/metrics (no port is no listener)
+//         --p <p>              [common] postgres login info (as JSON, a libpq connection string, or a postgresql:// URL)
+//                              [default: {"Host": "localhost", "Port": 5432, "User": "hippa_wr", "Pass": "", "DBName":
+//                              "hipparchiaDB"}]
+//         --r <r>              [common] redis login info (as JSON or as a redis://, rediss:// or unix:// URL) [default:
+//                              {"Addr": "localhost:6379", "Password": "", "DB": 0}]
+//         --t <t>              [common] number of workers to dispatch (also sizes the postgres and redis connection pools)
+//                              [default: 5]
+//
+// SUBCOMMANDS:
+//     bench        time the vectors stages and a reference set of searches over --svdb/--svs/--sve and report as JSON
+//     compare      line up two sets of hits or bags (e.g., from this helper and from the Go helper) and summarize the differences
+//     grab         execute the queries that HipparchiaServer left on the redis work pile and store the hits
+//     help         Prints this message or the help of the given subcommand(s)
+//     http         run a local HTTP JSON API for searches and vector jobs
+//     serve        stay up and take search and vector jobs off of redis
+//     vectors      build bags of words for the vectorizer
+//     websocket    run the websocket server that reports on the progress of searches
+//
+// grab:        --c <c> [searches] max hit count [default: 200]
+//              --k <k> [searches] redis key to use [default: rusttest]
+//
+// vectors:     --k <k>
+//              --svb <svb> the bagging method: alternates, flat, unlemmatized, winnertakesall [default: winnertakesall]
+//              --svbs <svbs> number of sentences per bag [default: 1]
+//              --svsh <svsh>, --svsi <svsi> headwords / inflected forms to drop from the bags (default: the built-in lists)
+//              --svdb <svdb>, --svs <svs>, --sve <sve> [for manual debugging] db and first/last line to grab
+//                  [defaults: lt0448, 1, 26]
+//              --svc <svc> read the author, morphology and headword count tables from the CSV/JSONL files in this
+//                  directory instead of from postgres (with --svdb/--svs/--sve: work items are SQL)
+//
+// websocket:   --wsh <wsh> IP address to open up [default: 127.0.0.1]
+//              --wsp <wsp> port [default: 5010]
+//              --wsf <wsf> fail threshold before messages stop being sent [default: 4]
+//              --wss <wss> save the polls instead of deleting them: 0 is no; 1 is yes [default: 0]
+//              --wsv <wsv> progress message schema: 1 is every field as a string on every tick; 2 is a typed snapshot
+//                  followed by deltas [default: 1]
+//              --wsx <wsx> also push the first N search hits to the browser as they are found (0 is off) [default: 0]
+//              --wstc <wstc>, --wstk <wstk> PEM certificate chain and (PKCS #8) key: serve wss:// instead of ws://
+//              --wsa <wsa> handshake token check: none, secret, hmac, redis [default: none]
+//              --wsk <wsk> shared secret for the 'secret' and 'hmac' token checks [default: ]
+//              --wso <wso> comma-separated list of allowed Origin values (empty allows any origin) [default: ]
+//
+// serve:       --sq <sq> redis list to take jobs from [default: hrh_jobs]
+//              --sj <sj> number of jobs to run at once (each gets --t workers) [default: 2]
+//              and the grab and vectors options above, as defaults for the jobs
+//
+// http:        --hh <hh> IP address to open up (anyone who can reach the API can run SQL: keep it local or set --hk)
+//                  [default: 127.0.0.1]
+//              --hp <hp> port on which to open the HTTP API [default: 5020]
+//              --hk <hk> require 'Authorization: Bearer <token>' with this token [default: ]
+//              --hkn <hkn> run without --hk, so that anyone who can reach the port can run SQL: 0 is no; 1 is yes
+//                  [default: 0]
+//              --sj <sj>, and the grab and vectors options above, as for serve
+//
+// compare:     --ca <ca>, --cb <cb> the two redis keys to compare (e.g., rusttest_vectorresults and _vectorresults)
+//              --cfa <cfa>, --cfb <cfb> or read a side from a file, one JSON object per line (redis-cli --raw SMEMBERS)
+//              --cx <cx> how many examples of each sort of difference to list [default: 10]
+//
+// bench:       --bn <bn> how many times to run everything [default: 5]
+//              --bs <bs> space-separated regexes to search for (default: a built-in Latin or Greek set; empty is no
+//                  searches)
+//              and the grab and vectors options above (but not --k: bench uses keys of its own)
+//
+// the legacy interface (what HipparchiaServer sends) takes all of the above without a subcommand:
+//     --ws is websocket, --sv is vectors, --v prints the version, and anything else is grab
 
 fn main() {
+    // a panic still leaves HipparchiaServer something to read: see theerrors.rs
+    er_panicreports();
+
     // cli stuff
     // .arg(Arg::with_name().long().takes_value().help())
-    let cli: ArgMatches = App::new(MYNAME)
+    //  every subcommand carries the common options; the bare top level is the old flag-driven interface that
+    //  HipparchiaServer still uses: "--ws" is websocket, "--sv" is vectors, and anything else is grab
+    let cli = App::new(MYNAME)
         .version(VERSION)
-        .arg(Arg::with_name("c")
-            .long("c")
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&commonargs())
+        .args(&legacyargs())
+        .subcommand(SubCommand::with_name("grab")
+            .about("execute the queries that HipparchiaServer left on the redis work pile and store the hits")
+            .args(&commonargs())
+            .arg(keyarg())
+            .args(&grabargs()))
+        .subcommand(SubCommand::with_name("vectors")
+            .about("build bags of words for the vectorizer")
+            .args(&commonargs())
+            .arg(keyarg())
+            .args(&vectorargs()))
+        .subcommand(SubCommand::with_name("websocket")
+            .about("run the websocket server that reports on the progress of searches")
+            .args(&commonargs())
+            .args(&websocketargs()))
+        .subcommand(SubCommand::with_name("serve")
+            .about("stay up and take search and vector jobs off of redis")
+            .args(&commonargs())
+            .args(&serveargs())
+            .args(&grabargs())
+            .args(&vectorargs()))
+        .subcommand(SubCommand::with_name("http")
+            .about("run a local HTTP JSON API for searches and vector jobs")
+            .args(&commonargs())
+            .args(&httpargs())
+            .args(&grabargs())
+            .args(&vectorargs()))
+        .subcommand(SubCommand::with_name("compare")
+            .about("line up two sets of hits or bags (e.g., from this helper and from the Go helper) and summarize the differences")
+            .args(&commonargs())
+            .args(&compareargs()))
+        .subcommand(SubCommand::with_name("bench")
+            .about("time the vectors stages and a reference set of searches over --svdb/--svs/--sve and report as JSON")
+            .args(&commonargs())
+            .args(&benchargs())
+            .args(&grabargs())
+            .args(&vectorargs()))
+        .get_matches_safe();
+
+    // --help and --version are not errors; a bad flag is a config error like any other
+    let cli: ArgMatches = match cli {
+        Ok(c) => c,
+        Err(e) if e.kind == ErrorKind::HelpDisplayed || e.kind == ErrorKind::VersionDisplayed => e.exit(),
+        Err(e) => {
+            let why = e.message.lines().next().unwrap_or("").trim_start_matches("error: ").to_string();
+            bailout(HelperError::Config(why))
+        }
+    };
+
+    match dispatch(cli) {
+        // python is going to read the resultkey, so you have to print it
+        // next has to be print! and not println!
+        Ok(Some(resultkey)) => print!("{}", resultkey),
+        Ok(None) => (),
+        Err(e) => bailout(e),
+    }
+}
+
+fn dispatch(cli: ArgMatches<'static>) -> HelperResult<Option<String>> {
+    // run the requested branch of the code; Some(resultkey) if there is something for python to read
+    let cfg = HelperConfig::load(cli)?;
+
+    let ll: i32 = cfg.parse("l")?;
+    let workers: i32 = cfg.parse("t")?;
+
+    // everything below logs to stderr (or --logfile); stdout is only for python
+    lg_init(ll, &cfg.text("logformat"), cfg.value("logfile").as_deref())?;
+
+    let m: String = format!("{} CLI Debugging Interface (v.{})", MYNAME, VERSION);
+    info!("{}", m);
+
+    if cfg.mode == HelperMode::Version {
+        print!("{}", m);
+        return Ok(None)
+    }
+
+    // the counters are written to --mf when this function returns, however it returns
+    let _dump = MtDump::new(cfg.value("mf"));
+    if let Some(port) = cfg.value("mp").filter(|p| !p.is_empty()) {
+        mt_serve(&cfg.text("mh"), &port)?;
+    }
+
+    // one set of warm connections for the whole run: every worker and every websocket client draws on it
+    let size = match cfg.mode {
+        HelperMode::Serve | HelperMode::Http => servepoolsize(workers, cfg.parse("sj")?),
+        _ => poolsize(workers),
+    };
+    let mut pools = DbPools::new(&cfg.text("p"), &cfg.text("r"), size)?.withformat(cfg.parse("compat")?);
+    if cfg.mode == HelperMode::Vectors || cfg.mode == HelperMode::Bench {
+        if let Some(dir) = cfg.value("svc").filter(|d| !d.is_empty()) {
+            pools = pools.withcorpus(Arc::new(FileCorpus::new(&dir)?));
+        }
+    }
+
+    if cfg.mode == HelperMode::Compare {
+        info!("requested the cp_compare() branch of the code");
+        let a = comparand(&cfg, "ca", "cfa", &pools)?;
+        let b = comparand(&cfg, "cb", "cfb", &pools)?;
+        let summary = cp_compare((&a.0, &a.1), (&b.0, &b.1), cfg.parse("cx")?)?;
+        // not a result key this time: the summary is the result
+        println!("{}", summary.pretty(2));
+        return Ok(None)
+    }
+
+    if cfg.mode == HelperMode::Websocket {
+        info!("requested the websocket() branch of the code");
+        // clap only checks that --wstc and --wstk come as a pair when both are flags
+        let tls = match (cfg.value("wstc"), cfg.value("wstk")) {
+            (Some(c), Some(k)) => Some((c, k)),
+            (None, None) => None,
+            _ => return Err(HelperError::Config("a TLS certificate (wstc) and key (wstk) have to be given together".to_string())),
+        };
+        let settings = WsSettings {
+            failthreshold: cfg.parse("wsf")?,
+            ip: cfg.text("wsh"),
+            port: cfg.text("wsp"),
+            save: cfg.parse("wss")?,
+            schema: cfg.parse("wsv")?,
+            hitstostream: cfg.parse("wsx")?,
+            pools: pools.clone(),
+            tls,
+        };
+        let gate = WsGatekeeper {
+            origins: cfg.text("wso").split(',').map(|o| o.trim().to_string()).filter(|o| !o.is_empty()).collect(),
+            mode: cfg.text("wsa"),
+            secret: cfg.text("wsk"),
+            pools: pools.clone(),
+        };
+        // note that websocket() will only return if it could not get started
+        websocket(settings, gate)?;
+        return Ok(None)
+    }
+
+    let thekey: String = cfg.text("k");
+
+    // find out now, and not halfway through, if this database is too old (or too odd) for us
+    let svdb: String = cfg.text("svdb");
+    let linetable: Option<&str> = match cfg.mode {
+        HelperMode::Vectors if thekey == TESTKEY => Some(&svdb),
+        HelperMode::Bench => Some(&svdb),
+        _ => None,
+    };
+    let schema = pools.corpus().open()?.schema(linetable)?;
+    debug!("database schema: HipparchiaBuilder {}", schema.generation);
+    schema.requirelines()?;
+
+    if cfg.mode == HelperMode::Bench {
+        schema.requiremorphology()?;
+        info!("requested the bench() branch of the code");
+        let settings = BenchSettings {
+            runs: cfg.parse("bn")?,
+            table: svdb,
+            start: cfg.parse("svs")?,
+            end: cfg.parse("sve")?,
+            bagging: cfg.text("svb"),
+            bagsize: cfg.parse("svbs")?,
+            stops: (cfg.value("svsh"), cfg.value("svsi")),
+            searches: cfg.value("bs").map(|s| s.split_whitespace().map(|t| t.to_string()).collect()),
+            cap: cfg.parse("c")?,
+            workers,
+        };
+        let report = bench(&settings, &pools)?;
+        // as with compare: the report is the result
+        println!("{}", report.pretty(2));
+        return Ok(None)
+    }
+
+    if cfg.mode == HelperMode::Serve {
+        info!("requested the daemon() branch of the code");
+        // vectors jobs check the morphology tables for themselves: a daemon that can only grab is still useful
+        daemon(cfg, workers, &pools, schema)?;
+        return Ok(None)
+    }
+
+    if cfg.mode == HelperMode::Http {
+        info!("requested the httpapi() branch of the code");
+        // note that httpapi() will only return if it could not get started
+        httpapi(cfg, workers, &pools, schema)?;
+        return Ok(None)
+    }
+
+    if cfg.mode == HelperMode::Vectors {
+        schema.requiremorphology()?;
+        info!("requested the vector_prep() branch of the code");
+        let b = cfg.text("svb");
+        let bs = cfg.parse("svbs")?;
+        let sta = cfg.parse("svs")?;
+        let end = cfg.parse("sve")?;
+        let stops = (cfg.value("svsh"), cfg.value("svsi"));
+        let resultkey: String = vector_prep(&thekey, &b, workers, bs, &svdb, sta, end, stops, &pools)?;
+        Ok(Some(resultkey))
+    } else {
+        // if neither "websocket" nor "vectors", then you are a "grabber"
+        info!("requested the grabber() branch of the code");
+        let cap: i32 = cfg.parse("c")?;
+        let resultkey: String = grabber(cap, thekey, workers, &pools)?;
+        Ok(Some(resultkey))
+    }
+}
+
+fn comparand(cfg: &HelperConfig, key: &str, file: &str, pools: &DbPools) -> HelperResult<(String, Vec<String>)> {
+    // one side of a comparison: (where it came from, the members) from a redis key or from a file
+    match (cfg.value(key), cfg.value(file)) {
+        (Some(k), None) => Ok((k.clone(), rs_smembers(&k, &mut RsConn::new(pools))?)),
+        (None, Some(f)) => Ok((f.clone(), cp_readfile(&f)?)),
+        _ => Err(HelperError::Config(format!("compare needs either --{} or --{}", key, file))),
+    }
+}
+
+fn cli_number(v: String) -> Result<(), String> {
+    // clap validator: turn "--t x" away before anything gets started
+    match v.parse::<i64>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("'{}' is not a number", v)),
+    }
+}
+
+fn commonargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("compat")
+            .long("compat")
             .takes_value(true)
-            .help("[searches] max hit count")
-            .default_value(HITSDEFAULT))
-        .arg(Arg::with_name("k")
-            .long("k")
+            .help("[common] write hits, bags and progress messages as this helper does ('rust') or as HipparchiaGoDBHelper does ('go')")
+            .possible_values(&["rust", "go"])
+            .default_value("rust"),
+        Arg::with_name("config")
+            .long("config")
             .takes_value(true)
-            .help("[searches] redis key to use")
-            .default_value(TESTKEY))
-        .arg(Arg::with_name("l")
+            .help("[common] TOML file of settings: overridden by $HRH_* and then by flags on the command line"),
+        Arg::with_name("l")
             .long("l")
             .takes_value(true)
-            .help("[common] logging level")
-            .default_value("0"))
-        .arg(Arg::with_name("p")
+            .validator(cli_number)
+            .help("[common] logging level: 0 is warnings and errors, 1 info, 2 debug, 3 trace")
+            .default_value("0"),
+        Arg::with_name("logfile")
+            .long("logfile")
+            .takes_value(true)
+            .help("[common] append the log to this file instead of writing it to stderr"),
+        Arg::with_name("logformat")
+            .long("logformat")
+            .takes_value(true)
+            .help("[common] log line format")
+            .possible_values(&["text", "json"])
+            .default_value("text"),
+        Arg::with_name("mf")
+            .long("mf")
+            .takes_value(true)
+            .help("[common] write the metrics (Prometheus text format) to this file when the run ends"),
+        Arg::with_name("mh")
+            .long("mh")
+            .takes_value(true)
+            .help("[common] IP address for the metrics listener")
+            .default_value("127.0.0.1"),
+        Arg::with_name("mp")
+            .long("mp")
+            .takes_value(true)
+            .validator(cli_number)
+            .help("[common] serve the metrics at http://--mh:--mp/metrics (no port is no listener)"),
+        Arg::with_name("p")
             .long("p")
             .takes_value(true)
-            .help("[common] postgres login info (as JSON)")
-            .default_value(PSQ))
-        .arg(Arg::with_name("r")
+            .help("[common] postgres login info (as JSON, a libpq connection string, or a postgresql:// URL)")
+            .default_value(PGDEFAULT),
+        Arg::with_name("r")
             .long("r")
             .takes_value(true)
-            .help("[common] redis login info (as JSON)")
-            .default_value(RP))
-        .arg(Arg::with_name("t")
+            .help("[common] redis login info (as JSON or as a redis://, rediss:// or unix:// URL)")
+            .default_value(RSDEFAULT),
+        Arg::with_name("t")
             .long("t")
             .takes_value(true)
-            .help("[common] number of workers to dispatch")
-            .default_value(WORKERSDEFAULT))
-        .arg(Arg::with_name("sv")
-            .long("sv")
-            .takes_value(false)
-            .help("[vectors] assert that this is a vectorizing run"))
-        .arg(Arg::with_name("svb")
+            .validator(cli_number)
+            .help("[common] number of workers to dispatch (also sizes the postgres and redis connection pools)")
+            .default_value(WORKERSDEFAULT),
+    ]
+}
+
+fn grabargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("c")
+            .long("c")
+            .takes_value(true)
+            .validator(cli_number)
+            .help("[searches] max hit count")
+            .default_value(HITSDEFAULT),
+    ]
+}
+
+fn keyarg() -> Arg<'static, 'static> {
+    Arg::with_name("k")
+        .long("k")
+        .takes_value(true)
+        .help("[searches] redis key to use")
+        .default_value(TESTKEY)
+}
+
+fn vectorargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("svb")
             .long("svb")
             .takes_value(true)
             .help("[vectors] the bagging method: choices are alternates, flat, unlemmatized, winnertakesall")
-            .default_value("winnertakesall"))
-        .arg(Arg::with_name("svbs")
+            .possible_values(&["alternates", "flat", "unlemmatized", "winnertakesall"])
+            .default_value("winnertakesall"),
+        Arg::with_name("svbs")
             .long("svbs")
             .takes_value(true)
+            .validator(cli_number)
             .help("[vectors] number of sentences per bag")
-            .default_value("1"))
-        .arg(Arg::with_name("svdb")
+            .default_value("1"),
+        Arg::with_name("svsh")
+            .long("svsh")
+            .takes_value(true)
+            .help("[vectors] space-separated headwords to drop from the bags (default: the built-in list)"),
+        Arg::with_name("svsi")
+            .long("svsi")
+            .takes_value(true)
+            .help("[vectors] space-separated inflected forms to drop from the bags (default: the built-in list)"),
+        Arg::with_name("svc")
+            .long("svc")
+            .takes_value(true)
+            .help("[vectors] read the author, morphology and headword count tables from the CSV/JSONL files in this directory"),
+        Arg::with_name("svdb")
             .long("svdb")
-            .takes_value(false)
+            .takes_value(true)
             .help("[vectors][for manual debugging] db to grab from")
-            .default_value(TESTDB))
-        .arg(Arg::with_name("sve")
+            .default_value(TESTDB),
+        Arg::with_name("sve")
             .long("sve")
-            .takes_value(false)
+            .takes_value(true)
+            .validator(cli_number)
             .help("[vectors][for manual debugging] last line to grab")
-            .default_value(TESTEND))
-        .arg(Arg::with_name("svs")
+            .default_value(TESTEND),
+        Arg::with_name("svs")
             .long("svs")
-            .takes_value(false)
+            .takes_value(true)
+            .validator(cli_number)
             .help("[vectors][for manual debugging] first line to grab")
-            .default_value(TESTSTART))
-        .arg(Arg::with_name("ws")
-            .long("ws")
-            .takes_value(false)
-            .help("[websockets] assert that you are requesting the websocket server"))
-        .arg(Arg::with_name("wsf")
+            .default_value(TESTSTART),
+    ]
+}
+
+fn websocketargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("wsf")
             .long("wsf")
             .takes_value(true)
+            .validator(cli_number)
             .help("[websockets] fail threshold before messages stop being sent")
-            .default_value("4"))
-        .arg(Arg::with_name("wsp")
+            .default_value("4"),
+        Arg::with_name("wsp")
             .long("wsp")
             .takes_value(true)
+            .validator(cli_number)
             .help("[websockets] port on which to open the websocket server")
-            .default_value("5010"))
-        .arg(Arg::with_name("wss")
+            .default_value("5010"),
+        Arg::with_name("wss")
             .long("wss")
             .takes_value(true)
             .help("[websockets] save the polls instead of deleting them: 0 is no; 1 is yes")
-            .default_value("0"))
-        .arg(Arg::with_name("wsh")
+            .possible_values(&["0", "1"])
+            .default_value("0"),
+        Arg::with_name("wsv")
+            .long("wsv")
+            .takes_value(true)
+            .help("[websockets] progress message schema: 1 is every field as a string on every tick; 2 is a typed snapshot followed by deltas")
+            .possible_values(&["1", "2"])
+            .default_value("1"),
+        Arg::with_name("wsx")
+            .long("wsx")
+            .takes_value(true)
+            .validator(cli_number)
+            .help("[websockets] also push the first N search hits to the browser as they are found (0 is off)")
+            .default_value("0"),
+        Arg::with_name("wsh")
             .long("wsh")
             .takes_value(true)
             .help("[websockets] IP address to open up")
-            .default_value("127.0.0.1"))
-        .arg(Arg::with_name("v")
-            .long("v")
-            .takes_value(false)
-            .help("[compatability] print version and exit"))
-        .get_matches();
-
-    let ft = cli.value_of("wsf").unwrap();
-    let ip = cli.value_of("wsh").unwrap();
-    let port = cli.value_of("wsp").unwrap();
+            .default_value("127.0.0.1"),
+        Arg::with_name("wstc")
+            .long("wstc")
+            .takes_value(true)
+            .requires("wstk")
+            .help("[websockets] PEM certificate chain: serve wss:// instead of ws:// (SIGHUP reloads it)"),
+        Arg::with_name("wstk")
+            .long("wstk")
+            .takes_value(true)
+            .requires("wstc")
+            .help("[websockets] PEM (PKCS #8) private key that goes with --wstc"),
+        Arg::with_name("wsa")
+            .long("wsa")
+            .takes_value(true)
+            .help("[websockets] handshake token check: choices are none, secret, hmac, redis")
+            .possible_values(&["none", "secret", "hmac", "redis"])
+            .default_value("none"),
+        Arg::with_name("wsk")
+            .long("wsk")
+            .takes_value(true)
+            .help("[websockets] shared secret for the 'secret' and 'hmac' token checks")
+            .default_value(""),
+        Arg::with_name("wso")
+            .long("wso")
+            .takes_value(true)
+            .help("[websockets] comma-separated list of allowed Origin values (empty allows any origin)")
+            .default_value(""),
+    ]
+}
 
-    let ll = cli.value_of("l").unwrap();
-    let ll: i32 = ll.parse().unwrap();
+fn serveargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("sq")
+            .long("sq")
+            .takes_value(true)
+            .help("[serve] redis list to take jobs from")
+            .default_value(QUEUEDEFAULT),
+        jobsarg(),
+    ]
+}
 
-    let t = cli.value_of("t").unwrap();
-    let workers: i32 = t.parse().unwrap();
+fn jobsarg() -> Arg<'static, 'static> {
+    Arg::with_name("sj")
+        .long("sj")
+        .takes_value(true)
+        .validator(cli_number)
+        .help("[serve] number of jobs to run at once (each gets --t workers)")
+        .default_value("2")
+}
 
-    let rc = cli.value_of("r").unwrap();
-    let pg = cli.value_of("p").unwrap();
+fn httpargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("hh")
+            .long("hh")
+            .takes_value(true)
+            .help("[http] IP address to open up (anyone who can reach the API can run SQL: keep it local or set --hk)")
+            .default_value("127.0.0.1"),
+        Arg::with_name("hp")
+            .long("hp")
+            .takes_value(true)
+            .validator(cli_number)
+            .help("[http] port on which to open the HTTP API")
+            .default_value("5020"),
+        Arg::with_name("hk")
+            .long("hk")
+            .takes_value(true)
+            .help("[http] require 'Authorization: Bearer <token>' with this token")
+            .default_value(""),
+        Arg::with_name("hkn")
+            .long("hkn")
+            .takes_value(true)
+            .help("[http] run without --hk, so that anyone who can reach the port can run SQL: 0 is no; 1 is yes")
+            .possible_values(&["0", "1"])
+            .default_value("0"),
+        jobsarg(),
+    ]
+}
 
-    let m: String = format!("{} CLI Debugging Interface (v.{})", MYNAME, VERSION);
-    lfl(m.clone(), ll, 1);
+fn compareargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("ca")
+            .long("ca")
+            .takes_value(true)
+            .conflicts_with("cfa")
+            .help("[compare] the first redis key to compare (e.g., rusttest_vectorresults)"),
+        Arg::with_name("cb")
+            .long("cb")
+            .takes_value(true)
+            .conflicts_with("cfb")
+            .help("[compare] the second redis key to compare"),
+        Arg::with_name("cfa")
+            .long("cfa")
+            .takes_value(true)
+            .help("[compare] read the first set from this file instead: one JSON object per line (redis-cli --raw SMEMBERS)"),
+        Arg::with_name("cfb")
+            .long("cfb")
+            .takes_value(true)
+            .help("[compare] read the second set from this file instead"),
+        Arg::with_name("cx")
+            .long("cx")
+            .takes_value(true)
+            .validator(cli_number)
+            .help("[compare] how many examples of each sort of difference to list")
+            .default_value("10"),
+    ]
+}
 
-    if cli.is_present("v") {
-        print!("{}", m);
-        process::exit(1);
-    }
+fn benchargs() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("bn")
+            .long("bn")
+            .takes_value(true)
+            .validator(cli_number)
+            .help("[bench] how many times to run everything")
+            .default_value(RUNSDEFAULT),
+        Arg::with_name("bs")
+            .long("bs")
+            .takes_value(true)
+            .help("[bench] space-separated regexes to search for (default: a built-in Latin or Greek set; empty is no searches)"),
+    ]
+}
 
-    if cli.is_present("ws") {
-        let m: String = format!("requested the websocket() branch of the code");
-        lfl(m, ll, 1);
-        let save = cli.value_of("wss").unwrap();
-        let s: i32 = save.parse().unwrap();
-        // note that websocket() will never return
-        websocket(ft, ll, ip, port, s, rc.to_string());
-    }
+fn legacyargs() -> Vec<Arg<'static, 'static>> {
+    // the pre-subcommand interface: still accepted, no longer advertised in --help
+    let mut legacy: Vec<Arg<'static, 'static>> = vec![
+        Arg::with_name("sv")
+            .long("sv")
+            .takes_value(false)
+            .help("[vectors] assert that this is a vectorizing run"),
+        Arg::with_name("ws")
+            .long("ws")
+            .takes_value(false)
+            .conflicts_with("sv")
+            .help("[websockets] assert that you are requesting the websocket server"),
+        Arg::with_name("v")
+            .long("v")
+            .takes_value(false)
+            .help("[compatability] print version and exit"),
+    ];
+    legacy.push(keyarg());
+    legacy.extend(grabargs());
+    legacy.extend(vectorargs());
+    legacy.extend(websocketargs());
+    legacy.into_iter().map(|a| a.hidden(true)).collect()
+}
 
-    let thekey: &str = cli.value_of("k").unwrap();
-    // python is going to read the resultkey, so you have to print it
-
-    if cli.is_present("sv") {
-        let m: String = format!("requested the vector_prep() branch of the code");
-        lfl(m, ll, 1);
-        let b = cli.value_of("svb").unwrap();
-        let bs = cli.value_of("svbs").unwrap().parse().unwrap();
-        let db = cli.value_of("svdb").unwrap();
-        let sta = cli.value_of("svs").unwrap().parse().unwrap();
-        let end = cli.value_of("sve").unwrap().parse().unwrap();
-        let resultkey: String = vector_prep(&thekey, &b, workers, bs, db, sta, end, ll, &pg, &rc);
-        // next has to be println! and not print!
-        print!("{}", resultkey);
-    } else {
-        // if neither "ws" or "vs", then you are a "grabber"
-        // note that a fn grabber() gets into a lifetime problem w/ thread::spawn()
-        let m: String = format!("requested the grabber() branch of the code");
-        lfl(m, ll, 1);
-        let resultkey: String = grabber(cli.clone(), thekey.to_string(), ll, workers, rc.to_string());
-        print!("{}", resultkey);
-    }
+fn bailout(e: HelperError) -> ! {
+    // python reads our stdout for a result key: say what went wrong somewhere else and quit
+    //  stderr gets one line of JSON; the exit status says what sort of failure it was (see theerrors.rs)
+    log::logger().flush();
+    eprintln!("{}", e.report());
+    std::process::exit(e.exitcode());
 }
@@ -4,12 +4,16 @@
 //        (see LICENSE in the top level directory of the distribution)
 
 use json::JsonValue;
-use redis::RedisResult;
+use log::trace;
 use regex::Regex;
 use std::collections::HashMap;
 
-use crate::thestructs::*;
 use crate::dbfunctions::*;
+use crate::thecompat::*;
+use crate::thecorpus::*;
+use crate::theerrors::*;
+use crate::thestorage::*;
+use crate::thestructs::*;
 
 pub fn sv_stripper(text: &str, topurge: Vec<Regex>) -> String {
     // https://github.com/rust-lang/regex/blob/master/examples/shootout-regex-dna-replace.rs
@@ -65,8 +69,7 @@ pub fn sv_swapper(text: &str) -> String {
     let newwords: Vec<&str> = words.iter()
         .map(|w| if swapper.contains_key(w) { swapper[w]} else { w })
         .collect();
-    let newtext = newwords.join(" ");
-    newtext
+    newwords.join(" ")
 }
 
 pub fn sv_acuteforgrave(text: String) -> String {
@@ -121,13 +124,13 @@ pub fn sv_buildsentences(splittext: Vec<&str>, bagsize: i32) -> HashMap<String,
 
     let mut splittext: Vec<&str> = splittext.into_iter().rev().collect();
 
-    while  splittext.len() > 0 {
+    while  !splittext.is_empty() {
         let mut parcel = String::new();
         for _ in 0..bagsize {
             parcel.push_str(splittext.pop().unwrap_or(""));
         }
         let lcs = parcel.to_lowercase();
-        let firsthit: String = match locc.captures(&lcs.as_str()) {
+        let firsthit: String = match locc.captures(lcs.as_str()) {
             None => "".to_string(),
             Some(x) => x[1].to_string(),
         };
@@ -188,7 +191,7 @@ pub fn sv_buildcompositebags(sentenceswithlocus: HashMap<String, String>, mm: Ha
         for w in words {
             if mm.contains_key(w) {
                 let yoked = mm[w].clone().join("·");
-                println!("{}: {}", w, yoked);
+                trace!("{}: {}", w, yoked);
                 newwords.push(yoked.clone());
             }
         }
@@ -209,7 +212,7 @@ pub fn sv_buildcompositebags(sentenceswithlocus: HashMap<String, String>, mm: Ha
     bagged
 }
 
-pub fn sv_buildwinnertakesallbags(sentenceswithlocus: HashMap<String, String>, parsemap: HashMap<String, Vec<String>>, pg: &mut postgres::Client) -> HashMap<String, String> {
+pub fn sv_buildwinnertakesallbags(sentenceswithlocus: HashMap<String, String>, parsemap: HashMap<String, Vec<String>>, db: &mut dyn CorpusConn) -> HelperResult<HashMap<String, String>> {
     // turn a list of sentences into a list of list of headwords; here we figure out which headword is the dominant homonym
     // then we just use that term; "esse" always comes from "sum" and never "edo", etc.
 
@@ -223,8 +226,8 @@ pub fn sv_buildwinnertakesallbags(sentenceswithlocus: HashMap<String, String>, p
     }
 
     // [b] generate scoremap and assign scores to each of the headwords
-    let wds: Vec<String> = allheadwords.keys().map(|k| k.clone()).collect();
-    let scoremap: HashMap<String, i32> = db_fetchheadwordcounts(wds, pg);
+    let wds: Vec<String> = allheadwords.keys().cloned().collect();
+    let scoremap: HashMap<String, i32> = db_fetchheadwordcounts(wds, db)?;
 
     // for s in scoremap.keys() {
     //     println!("{} {}", &s, &scoremap[s]);
@@ -299,7 +302,7 @@ pub fn sv_buildwinnertakesallbags(sentenceswithlocus: HashMap<String, String>, p
     //     println!("{}", b);
     // }
 
-    bagged
+    Ok(bagged)
 }
 
 pub fn sv_findallwords(sentences: Vec<&str>) -> Vec<&str> {
@@ -310,11 +313,11 @@ pub fn sv_findallwords(sentences: Vec<&str>) -> Vec<&str> {
             allwords.insert(w, true);
         }
     };
-    let thewords = allwords.keys().map(|x| *x ).collect();
+    let thewords = allwords.keys().copied().collect();
     thewords
 }
 
-pub fn sv_getrequiredmorphobjects(words: Vec<&str>, pg: &mut postgres::Client) -> Vec<DbMorphology> {
+pub fn sv_getrequiredmorphobjects(words: Vec<&str>, db: &mut dyn CorpusConn) -> HelperResult<Vec<DbMorphology>> {
     // we need DbMorphology to build our bags; grab it
     let latintest = Regex::new("[a-z]+").unwrap();
     // let greektest = Regex::new("[α-ωϲἀἁἂἃἄἅἆἇᾀᾁᾂᾃᾄᾅᾆᾇᾲᾳᾴᾶᾷᾰᾱὰάἐἑἒἓἔἕὲέἰἱἲἳἴἵἶἷὶίῐῑῒΐῖῗὀὁὂὃὄὅόὸὐὑὒὓὔὕὖὗϋῠῡῢΰῦῧύὺᾐᾑᾒᾓᾔᾕᾖᾗῂῃῄῆῇἤἢἥἣὴήἠἡἦἧὠὡὢὣὤὥὦὧᾠᾡᾢᾣᾤᾥᾦᾧῲῳῴῶῷώὼ]+").unwrap();
@@ -329,11 +332,39 @@ pub fn sv_getrequiredmorphobjects(words: Vec<&str>, pg: &mut postgres::Client) -
         }
     }
 
-    let mut morph: Vec<DbMorphology> = db_sv_get_morphobjects(&mut latinwords, "latin", pg);
-    let mut grmorph: Vec<DbMorphology> = db_sv_get_morphobjects(&mut greekwords, "greek", pg);
+    let mut morph: Vec<DbMorphology> = db_sv_get_morphobjects(&mut latinwords, "latin", db)?;
+    let mut grmorph: Vec<DbMorphology> = db_sv_get_morphobjects(&mut greekwords, "greek", db)?;
 
     morph.append(&mut grmorph);
-    morph
+    Ok(morph)
+}
+
+pub fn sv_buildmorphmap(mo: Vec<DbMorphology>, words: &[&str]) -> HashMap<String, Vec<String>> {
+    // see convertmophdicttodict()
+    // a set of sets
+    //	key = word-in-use
+    //	value = { maybeA, maybeB, maybeC}
+    // {'θεῶν': {'θεόϲ', 'θέα', 'θεάω', 'θεά'}, 'πώ': {'πω'}, 'πολλά': {'πολύϲ'}, 'πατήρ': {'πατήρ'}, ... }
+    let mut morphmap: HashMap<String, Vec<String>> = HashMap::new();
+    for m in mo {
+        morphmap.insert(m.obs, m.upo);
+    }
+
+    // retain unparsed terms
+    for w in words {
+        if !morphmap.contains_key(*w) {
+            morphmap.insert(w.to_string(), vec![w.to_string()]);
+        }
+    }
+
+    // note that capitalization issues mean that your morphmap can be longer than the total number of words
+    morphmap
+}
+
+pub fn sv_lookupmorphology(words: &[&str], db: &mut dyn CorpusConn) -> HelperResult<HashMap<String, Vec<String>>> {
+    // word --> the headwords it might belong to, in one call: an unparsed word is its own headword
+    let mo = sv_getrequiredmorphobjects(words.to_vec(), db)?;
+    Ok(sv_buildmorphmap(mo, words))
 }
 
 pub fn sv_dropstopwords(todrop: &str, bags: HashMap<String, String>) -> HashMap<String, String> {
@@ -358,20 +389,20 @@ pub fn sv_dropstopwords(todrop: &str, bags: HashMap<String, String>) -> HashMap<
     cleaned
 }
 
-pub fn sv_loadthebags(key: String, bags: HashMap<String, String>, rca: &str) {
+pub fn sv_loadthebags(key: &str, bags: &HashMap<String, String>, f: HelperFormat, c: &mut RsConn) -> RsResult<()> {
     // load the bags of words into redis
     // on the python end: hits = {j['Loc']: j['Bag'] for j in js}
-    let mut c = redisconnect(rca.to_string());
 
-    let mut pipe = redis::pipe();
+    let mut members: Vec<String> = Vec::new();
     for b in bags.keys() {
         let mut data = JsonValue::new_object();
         data["Loc"] = b.clone().into();
         data["Bag"] = bags[b].clone().into();
         // print!["{}", data.dump()];
-        pipe.cmd("SADD").arg(key.as_str()).arg(data.dump()).ignore();
+        members.push(cp_dump(&data, f));
     }
-    let _: RedisResult<()> = pipe.query(&mut c);
+    // one SADD for the lot
+    rs_saddmany(key, &members, c)
 }
 
 pub fn _sv_parallelmorphology() {
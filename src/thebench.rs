@@ -0,0 +1,249 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use json::number::Number;
+use json::JsonValue;
+use log::{info, warn};
+use uuid::Uuid;
+
+use crate::thegrabber::*;
+use crate::theerrors::*;
+use crate::thepools::*;
+use crate::thestorage::*;
+use crate::thevectors::*;
+
+// the timing runs in NOTES.txt, done for us: "bench --svdb lt0474 --svs 4 --sve 140000 --bn 5"
+//
+//  [a] the standard vectors pipeline over --svdb from --svs to --sve, --bn times
+//  [b] a reference set of searches over the same lines, --bn times each: one work item per worker, as HipparchiaServer
+//      splits up a big search
+//  [c] for each stage the min, median and max of the [A: ...] ... [J: ...] checkpoints (the time since the start) and of
+//      the time spent in the stage itself; for each search the same for the whole grab
+//  [d] the memory high-water (VmHWM) of every run: on linux the peak is reset before each run, elsewhere it is null
+//
+// everything is written under keys of its own (BENCHKEY_<uuid>_...) and deleted afterwards: the uuid keeps two benches
+// against the same redis from storing into (and deleting) each other's bags and hits
+
+static BENCHKEY: &str = "hrhbench";
+
+// what to look for if --bs does not say: common words, a stem and something rarer
+static BENCHLATIN: &str = "et quod bell amicitia";
+static BENCHGREEK: &str = "και λογοϲ θεο φιλια";
+
+pub struct BenchSettings {
+    pub runs: usize,
+    pub table: String,
+    pub start: i32,
+    pub end: i32,
+    pub bagging: String,
+    pub bagsize: i32,
+    pub stops: (Option<String>, Option<String>),
+    // None is the built-in list for the table's language; an empty list skips the searches
+    pub searches: Option<Vec<String>>,
+    pub cap: i32,
+    pub workers: i32,
+}
+
+pub fn bench(settings: &BenchSettings, pools: &DbPools) -> HelperResult<JsonValue> {
+    // the report: {"Version": ..., "Key": "hrhbench_<uuid>", "Vectors": {"Stages": [...], ...}, "Searches": [...], ...}
+    let runs = settings.runs.max(1);
+    let terms = bn_searchterms(settings, pools)?;
+    let prefix = format!("{}_{}", BENCHKEY, Uuid::new_v4().to_simple());
+    let resets = bn_resethighwater();
+
+    // [a] vectors
+    let pipeline = BagPipeline::standard(&settings.bagging, settings.bagsize, &settings.table, settings.start, settings.end, settings.stops.clone())?;
+    // (label, what it said last time, checkpoints, time in the stage), in pipeline order
+    let mut laps: Vec<(String, String, Vec<Duration>, Vec<Duration>)> = Vec::new();
+    let mut totals: Vec<Duration> = Vec::new();
+    let mut vectormemory: Vec<Option<u64>> = Vec::new();
+    for r in 0..runs {
+        let key = format!("{}_v{}", prefix, r);
+        bn_resethighwater();
+        let st = pipeline.execute(&key, pools);
+        vectormemory.push(bn_highwater());
+        bn_forget(&key, pools);
+        let st = st?;
+
+        let mut previous = Duration::ZERO;
+        for (label, said, at) in &st.laps {
+            let i = match laps.iter().position(|l| &l.0 == label) {
+                Some(i) => i,
+                None => {
+                    laps.push((label.clone(), String::new(), Vec::new(), Vec::new()));
+                    laps.len() - 1
+                }
+            };
+            laps[i].1 = said.clone();
+            laps[i].2.push(*at);
+            laps[i].3.push(at.saturating_sub(previous));
+            previous = *at;
+        }
+        totals.push(previous);
+        info!("bench: vectors run {} of {} took {:.3}s", r + 1, runs, previous.as_secs_f64());
+    }
+
+    let mut stages = JsonValue::new_array();
+    for (label, said, at, spent) in &laps {
+        let mut s = JsonValue::new_object();
+        s["Stage"] = label.as_str().into();
+        s["Said"] = said.as_str().into();
+        s["At"] = bn_spread(at);
+        s["Spent"] = bn_spread(spent);
+        let _ = stages.push(s);
+    }
+    let mut vectors = JsonValue::new_object();
+    vectors["Bagging"] = settings.bagging.as_str().into();
+    vectors["Bagsize"] = settings.bagsize.into();
+    vectors["Stages"] = stages;
+    vectors["Total"] = bn_spread(&totals);
+    vectors["HighWater"] = bn_maxmemory(&vectormemory);
+
+    // [b] searches
+    let mut searches = JsonValue::new_array();
+    for t in &terms {
+        let mut times: Vec<Duration> = Vec::new();
+        let mut memory: Vec<Option<u64>> = Vec::new();
+        let mut hits: usize = 0;
+        for r in 0..runs {
+            let key = format!("{}_s{}", prefix, r);
+            bn_resethighwater();
+            let (took, found) = bn_search(&key, t, settings, pools)?;
+            memory.push(bn_highwater());
+            times.push(took);
+            hits = found;
+        }
+        info!("bench: '{}' found {} hits", t, hits);
+        let mut s = JsonValue::new_object();
+        s["Search"] = t.as_str().into();
+        s["Hits"] = hits.into();
+        s["Seconds"] = bn_spread(&times);
+        s["HighWater"] = bn_maxmemory(&memory);
+        let _ = searches.push(s);
+    }
+
+    let mut report = JsonValue::new_object();
+    report["Version"] = env!("CARGO_PKG_VERSION").into();
+    report["Key"] = prefix.as_str().into();
+    report["Table"] = settings.table.as_str().into();
+    report["Start"] = settings.start.into();
+    report["End"] = settings.end.into();
+    report["Runs"] = runs.into();
+    report["Workers"] = settings.workers.into();
+    report["Vectors"] = vectors;
+    report["Searches"] = searches;
+    // is "HighWater" per run (true) or the peak of the whole process so far (false)?
+    report["HighWaterPerRun"] = resets.into();
+    Ok(report)
+}
+
+fn bn_searchterms(settings: &BenchSettings, pools: &DbPools) -> HelperResult<Vec<String>> {
+    // what [b] will look for, decided before [a] starts: a search that cannot run should not cost a set of vector runs first
+    //  the --svc files cannot run SQL, so the built-in searches are left out; searches that were asked for are an error
+    let searchable = pools.corpus().searches();
+    match &settings.searches {
+        Some(t) if !t.is_empty() && !searchable => Err(HelperError::UnsupportedMode(format!(
+            "{} cannot run the reference searches: drop --bs or use postgres", pools.corpus().describe()))),
+        Some(t) => Ok(t.clone()),
+        None if !searchable => {
+            warn!("bench: {} cannot search, so there are no reference searches", pools.corpus().describe());
+            Ok(Vec::new())
+        }
+        None => {
+            let builtin = if settings.table.starts_with("lt") { BENCHLATIN } else { BENCHGREEK };
+            Ok(builtin.split_whitespace().map(|t| t.to_string()).collect())
+        }
+    }
+}
+
+fn bn_search(key: &str, term: &str, settings: &BenchSettings, pools: &DbPools) -> HelperResult<(Duration, usize)> {
+    // one search, split into one work item per worker; (how long grabber() took, how many hits it stored)
+    let mut redisconn = RsConn::new(pools);
+    let items = bn_workitems(term, settings);
+    let r = rs_saddmany(key, &items, &mut redisconn).map_err(HelperError::from)
+        .and_then(|_| {
+            let start = Instant::now();
+            let resultkey = grabber(settings.cap, key.to_string(), settings.workers, pools)?;
+            let took = start.elapsed();
+            let hits = rs_scard(&resultkey, &mut redisconn)?;
+            Ok((took, hits.max(0) as usize))
+        });
+    bn_forget(key, pools);
+    r
+}
+
+fn bn_workitems(term: &str, settings: &BenchSettings) -> Vec<String> {
+    // {"TempTable": "", "PsqlQuery": "SELECT * FROM lt0474 WHERE (index BETWEEN 4 AND 35002) AND stripped_line ~* $1 ...", "PsqlData": "amicitia"}
+    let chunks = settings.workers.max(1);
+    let span = (settings.end - settings.start + 1).max(1);
+    let size = (span + chunks - 1) / chunks;
+    let mut items: Vec<String> = Vec::new();
+    let mut first = settings.start;
+    while first <= settings.end {
+        let last = (first + size - 1).min(settings.end);
+        let mut j = JsonValue::new_object();
+        j["TempTable"] = "".into();
+        j["PsqlQuery"] = format!("SELECT * FROM {} WHERE (index BETWEEN {} AND {}) AND stripped_line ~* $1 ORDER BY index ASC LIMIT {}",
+                                 settings.table, first, last, settings.cap).into();
+        j["PsqlData"] = term.into();
+        items.push(j.dump());
+        first = last + 1;
+    }
+    items
+}
+
+fn bn_forget(key: &str, pools: &DbPools) {
+    // a benchmark should not leave anything behind (or find its last run's bags and hits)
+    let mut redisconn = RsConn::new(pools);
+    for suffix in ["", "_results", "_vectorresults", "_poolofwork", "_remaining", "_hitcount"] {
+        if let Err(e) = rs_del(&format!("{}{}", key, suffix), &mut redisconn) {
+            warn!("bench could not delete {}{}: {}", key, suffix, e);
+        }
+    }
+}
+
+fn bn_spread(d: &[Duration]) -> JsonValue {
+    // {"Min": 0.341563, "Median": 0.398452, "Max": 0.512001}, in seconds
+    let mut s: Vec<u128> = d.iter().map(|d| d.as_micros()).collect();
+    s.sort_unstable();
+    let mut j = JsonValue::new_object();
+    if s.is_empty() {
+        return j
+    }
+    let n = s.len();
+    let median = if n % 2 == 1 { s[n / 2] } else { (s[n / 2 - 1] + s[n / 2]) / 2 };
+    j["Min"] = bn_seconds(s[0]);
+    j["Median"] = bn_seconds(median);
+    j["Max"] = bn_seconds(s[n - 1]);
+    j
+}
+
+fn bn_seconds(micros: u128) -> JsonValue {
+    // microseconds are plenty; as an exact decimal so that 1579µs is 0.001579 and not 1.5790000000000002e-3
+    Number::from_parts(true, micros as u64, -6).into()
+}
+
+fn bn_maxmemory(m: &[Option<u64>]) -> JsonValue {
+    match m.iter().flatten().max() {
+        Some(b) => (*b).into(),
+        None => JsonValue::Null,
+    }
+}
+
+fn bn_highwater() -> Option<u64> {
+    // VmHWM in /proc/self/status is the peak resident set size: "VmHWM:	  123456 kB"
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn bn_resethighwater() -> bool {
+    // writing 5 to clear_refs sets VmHWM back to the current RSS (linux 4.0+)
+    fs::write("/proc/self/clear_refs", "5").is_ok()
+}
@@ -0,0 +1,377 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::str::FromStr;
+
+use json::JsonValue;
+
+use crate::theerrors::*;
+
+// can the Rust helper stand in for HipparchiaGoDBHelper?
+//
+//  [a] --compat go writes the hits, the bags and the (schema 1) progress messages byte for byte as the Go helper does
+//  [b] "compare" lines up two sets of results (e.g., "<key>_vectorresults" from each helper) and says how they differ
+//
+// the field names and their order are already the same in both helpers:
+//      hits:   {"WkUID":"lt0448w001","TbIndex":1,"Lvl5Value":"-1",...,"Hypenated":"","Annotations":""}
+//      bags:   {"Loc":"line/lt0448w001/1","Bag":"gallia divido ..."}
+//      polls:  {"Active":"yes","Hitcount":"12","ID":"ebf24e19",...}
+// what is not: Go's encoding/json escapes <, > and & (MarkedUp is HTML), U+2028/U+2029 and every control character
+// but \n, \r and \t as \u00XX; and a Go map (the progress message) comes out with its keys sorted
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HelperFormat {
+    // what this helper has always written
+    Rust,
+    // what HipparchiaGoDBHelper writes
+    Go,
+}
+
+impl FromStr for HelperFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HelperFormat, String> {
+        match s {
+            "rust" => Ok(HelperFormat::Rust),
+            "go" => Ok(HelperFormat::Go),
+            _ => Err(format!("'{}' is not an output format: choose rust or go", s)),
+        }
+    }
+}
+
+pub fn cp_dump(j: &JsonValue, f: HelperFormat) -> String {
+    match f {
+        HelperFormat::Rust => j.dump(),
+        HelperFormat::Go => cp_gojson(j),
+    }
+}
+
+pub fn cp_gojson(j: &JsonValue) -> String {
+    // json.Marshal(): no whitespace, fields in the order given, strings escaped as Go escapes them
+    let mut out = String::new();
+    cp_gowrite(j, &mut out);
+    out
+}
+
+fn cp_gowrite(j: &JsonValue, out: &mut String) {
+    match j {
+        JsonValue::String(_) | JsonValue::Short(_) => cp_gostring(j.as_str().unwrap_or_default(), out),
+        JsonValue::Array(a) => {
+            out.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                cp_gowrite(v, out);
+            }
+            out.push(']');
+        }
+        JsonValue::Object(_) => {
+            out.push('{');
+            for (i, (k, v)) in j.entries().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                cp_gostring(k, out);
+                out.push(':');
+                cp_gowrite(v, out);
+            }
+            out.push('}');
+        }
+        // null, true, false and numbers look the same in both
+        _ => out.push_str(&j.dump()),
+    }
+}
+
+fn cp_gostring(s: &str, out: &mut String) {
+    // encoding/json's encodeState.string() with escapeHTML on
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub fn cp_sortedobject(j: &JsonValue) -> JsonValue {
+    // a Go map[string]string is marshalled with its keys in order
+    let mut keys: Vec<&str> = j.entries().map(|(k, _)| k).collect();
+    keys.sort_unstable();
+    let mut sorted = JsonValue::new_object();
+    for k in keys {
+        sorted[k] = j[k].clone();
+    }
+    sorted
+}
+
+// [b] compare
+//
+// a result set is a list of JSON objects, as SMEMBERS returns them (or as "redis-cli --raw SMEMBERS <key> > file" saves them)
+//  bags are matched on "Loc" and hits on "WkUID" + "TbIndex"; then the two sides are compared item by item:
+//      Identical   - the same bytes
+//      Equivalent  - the same values, written differently (e.g., \u003c for <)
+//      Reordered   - a bag with the same words in a different order
+//      Different   - anything else
+//  for bags the words are counted as well: the words whose counts differ most are where the morphology disagrees
+
+#[derive(Clone, Copy, PartialEq)]
+enum CpKind {
+    Bags,
+    Hits,
+}
+
+struct CpItem {
+    raw: String,
+    parsed: JsonValue,
+}
+
+struct CpSide {
+    source: String,
+    items: BTreeMap<String, CpItem>,
+    duplicates: usize,
+}
+
+pub fn cp_readfile(path: &str) -> HelperResult<Vec<String>> {
+    // one JSON object per line; blank lines are skipped
+    let text = fs::read_to_string(path).map_err(|e| HelperError::Config(format!("cannot read {}: {}", path, e)))?;
+    Ok(text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).map(|l| l.to_string()).collect())
+}
+
+pub fn cp_compare(a: (&str, &[String]), b: (&str, &[String]), examples: usize) -> HelperResult<JsonValue> {
+    // (where it came from, the members) for each side; the summary is JSON
+    let (ka, sa) = cp_side(a.0, a.1)?;
+    let (kb, sb) = cp_side(b.0, b.1)?;
+    let kind = match (ka, kb) {
+        (Some(x), Some(y)) if x != y => return Err(HelperError::Config(format!("{} holds {} but {} holds {}", sa.source, cp_kindname(x), sb.source, cp_kindname(y)))),
+        (Some(x), _) | (None, Some(x)) => x,
+        (None, None) => CpKind::Bags,
+    };
+
+    let onlya: Vec<&String> = sa.items.keys().filter(|k| !sb.items.contains_key(*k)).collect();
+    let onlyb: Vec<&String> = sb.items.keys().filter(|k| !sa.items.contains_key(*k)).collect();
+
+    // [b1] item by item
+    let mut tally: Vec<(&str, usize)> = ["Identical", "Equivalent", "Reordered", "Different"].iter().map(|t| (*t, 0)).collect();
+    let mut fields: BTreeMap<String, usize> = BTreeMap::new();
+    let mut different = JsonValue::new_array();
+    for (k, x) in &sa.items {
+        let y = match sb.items.get(k) {
+            Some(y) => y,
+            None => continue,
+        };
+        let verdict = if x.raw == y.raw {
+            "Identical"
+        } else if x.parsed == y.parsed {
+            "Equivalent"
+        } else if kind == CpKind::Bags && cp_sortedwords(&x.parsed) == cp_sortedwords(&y.parsed) {
+            "Reordered"
+        } else {
+            "Different"
+        };
+        tally.iter_mut().find(|(t, _)| *t == verdict).unwrap().1 += 1;
+        if verdict != "Different" {
+            continue
+        }
+        let mut d = JsonValue::new_object();
+        d["Key"] = k.as_str().into();
+        match kind {
+            CpKind::Bags => {
+                let (wa, wb) = cp_worddifference(&x.parsed, &y.parsed);
+                d["OnlyA"] = wa.into();
+                d["OnlyB"] = wb.into();
+            }
+            CpKind::Hits => {
+                let names = cp_fielddifference(&x.parsed, &y.parsed);
+                for n in &names {
+                    *fields.entry(n.clone()).or_insert(0) += 1;
+                }
+                d["Fields"] = names.into();
+            }
+        }
+        if different.len() < examples {
+            let _ = different.push(d);
+        }
+    }
+
+    // [b2] the bags that only moved: the same words under another locus (e.g., where the sentences were cut differently)
+    let contents = |s: &CpSide, keys: &[&String]| -> HashMap<Vec<String>, usize> {
+        let mut m: HashMap<Vec<String>, usize> = HashMap::new();
+        for k in keys {
+            *m.entry(cp_sortedwords(&s.items[*k].parsed)).or_insert(0) += 1;
+        }
+        m
+    };
+    let moved: usize = match kind {
+        CpKind::Bags => {
+            let (ca, cb) = (contents(&sa, &onlya), contents(&sb, &onlyb));
+            ca.iter().map(|(w, n)| (*n).min(*cb.get(w).unwrap_or(&0))).sum()
+        }
+        CpKind::Hits => 0,
+    };
+
+    let mut summary = JsonValue::new_object();
+    summary["Kind"] = cp_kindname(kind).into();
+    summary["A"] = cp_sidesummary(&sa, kind);
+    summary["B"] = cp_sidesummary(&sb, kind);
+
+    let mut keys = JsonValue::new_object();
+    keys["Both"] = (sa.items.len() - onlya.len()).into();
+    keys["OnlyA"] = onlya.len().into();
+    keys["OnlyB"] = onlyb.len().into();
+    if kind == CpKind::Bags {
+        keys["Moved"] = moved.into();
+    }
+    summary["Keys"] = keys;
+
+    let mut content = JsonValue::new_object();
+    for (t, n) in &tally {
+        if *t != "Reordered" || kind == CpKind::Bags {
+            content[*t] = (*n).into();
+        }
+    }
+    summary["Content"] = content;
+
+    if kind == CpKind::Hits {
+        let mut f = JsonValue::new_object();
+        for (n, c) in &fields {
+            f[n.as_str()] = (*c).into();
+        }
+        summary["Fields"] = f;
+    } else {
+        summary["Words"] = cp_wordcounts(&sa, &sb, examples);
+    }
+
+    let mut ex = JsonValue::new_object();
+    ex["OnlyA"] = onlya.iter().take(examples).map(|k| k.as_str()).collect::<Vec<&str>>().into();
+    ex["OnlyB"] = onlyb.iter().take(examples).map(|k| k.as_str()).collect::<Vec<&str>>().into();
+    ex["Different"] = different;
+    summary["Examples"] = ex;
+
+    let unequal: usize = tally.iter().filter(|(t, _)| *t == "Reordered" || *t == "Different").map(|(_, n)| n).sum();
+    summary["Same"] = (onlya.is_empty() && onlyb.is_empty() && unequal == 0).into();
+    Ok(summary)
+}
+
+fn cp_kindname(k: CpKind) -> &'static str {
+    match k {
+        CpKind::Bags => "bags",
+        CpKind::Hits => "hits",
+    }
+}
+
+fn cp_side(source: &str, members: &[String]) -> HelperResult<(Option<CpKind>, CpSide)> {
+    // key every member; they all have to be the same sort of thing
+    //  SMEMBERS comes back in any order: sorted, the same member wins a duplicated key every time
+    let mut kind: Option<CpKind> = None;
+    let mut side = CpSide { source: source.to_string(), items: BTreeMap::new(), duplicates: 0 };
+    let mut members: Vec<&String> = members.iter().collect();
+    members.sort();
+    for m in members {
+        let parsed = json::parse(m).map_err(|e| HelperError::Config(format!("{}: not JSON ({}): {}", source, e, m)))?;
+        let (k, key) = if parsed["Loc"].is_string() && parsed["Bag"].is_string() {
+            (CpKind::Bags, parsed["Loc"].to_string())
+        } else if parsed["WkUID"].is_string() && !parsed["TbIndex"].is_null() {
+            (CpKind::Hits, format!("{}/{}", parsed["WkUID"], parsed["TbIndex"]))
+        } else {
+            return Err(HelperError::Config(format!("{}: neither a bag nor a hit: {}", source, m)))
+        };
+        match kind {
+            Some(seen) if seen != k => return Err(HelperError::Config(format!("{} mixes bags and hits", source))),
+            _ => kind = Some(k),
+        }
+        match side.items.entry(key) {
+            Entry::Occupied(_) => side.duplicates += 1,
+            Entry::Vacant(v) => { v.insert(CpItem { raw: m.clone(), parsed }); }
+        }
+    }
+    Ok((kind, side))
+}
+
+fn cp_sidesummary(s: &CpSide, kind: CpKind) -> JsonValue {
+    let mut j = JsonValue::new_object();
+    j["Source"] = s.source.as_str().into();
+    j["Items"] = s.items.len().into();
+    j["Duplicates"] = s.duplicates.into();
+    if kind == CpKind::Bags {
+        let counts = cp_counts(s);
+        j["Words"] = counts.values().sum::<usize>().into();
+        j["Distinct"] = counts.len().into();
+    }
+    j
+}
+
+fn cp_sortedwords(bag: &JsonValue) -> Vec<String> {
+    let mut w: Vec<String> = bag["Bag"].as_str().unwrap_or_default().split_whitespace().map(|w| w.to_string()).collect();
+    w.sort_unstable();
+    w
+}
+
+fn cp_worddifference(x: &JsonValue, y: &JsonValue) -> (Vec<String>, Vec<String>) {
+    // the words (with repeats) that one bag has and the other does not
+    let mut wb = cp_sortedwords(y);
+    let mut wa: Vec<String> = Vec::new();
+    for w in cp_sortedwords(x) {
+        match wb.iter().position(|v| *v == w) {
+            Some(i) => { wb.remove(i); }
+            None => wa.push(w),
+        }
+    }
+    (wa, wb)
+}
+
+fn cp_fielddifference(x: &JsonValue, y: &JsonValue) -> Vec<String> {
+    // the fields of a hit that do not match (or are only on one side)
+    let mut names: Vec<String> = x.entries().map(|(k, _)| k.to_string())
+        .chain(y.entries().map(|(k, _)| k.to_string()))
+        .filter(|k| x[k.as_str()] != y[k.as_str()])
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn cp_counts(s: &CpSide) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for i in s.items.values() {
+        for w in i.parsed["Bag"].as_str().unwrap_or_default().split_whitespace() {
+            *counts.entry(w.to_string()).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+fn cp_wordcounts(a: &CpSide, b: &CpSide, examples: usize) -> JsonValue {
+    // [{"Word": "sum¹", "A": 120, "B": 97}, ...]: the biggest differences first
+    let (ca, cb) = (cp_counts(a), cp_counts(b));
+    let every: BTreeSet<&String> = ca.keys().chain(cb.keys()).collect();
+    let mut words: Vec<(&String, usize, usize)> = every.into_iter()
+        .map(|w| (w, *ca.get(w).unwrap_or(&0), *cb.get(w).unwrap_or(&0)))
+        .filter(|(_, x, y)| x != y)
+        .collect();
+    // a stable sort, so the ties stay in alphabetical order
+    words.sort_by_key(|(_, x, y)| std::cmp::Reverse(x.abs_diff(*y)));
+
+    let mut j = JsonValue::new_array();
+    for (w, x, y) in words.into_iter().take(examples) {
+        let mut e = JsonValue::new_object();
+        e["Word"] = w.as_str().into();
+        e["A"] = x.into();
+        e["B"] = y.into();
+        let _ = j.push(e);
+    }
+    j
+}
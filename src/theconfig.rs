@@ -0,0 +1,232 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use json::JsonValue;
+use toml::Value;
+
+use crate::theerrors::*;
+
+// a setting can come from four places; the later ones win:
+//      clap default < config file (--config or $HRH_CONFIG) < $HRH_* < a flag given on the command line
+//
+// flag, [section] and key in the config file, environment variable
+static SETTINGS: [(&str, &str, &str, &str); 35] = [
+    ("t", "common", "workers", "HRH_WORKERS"),
+    ("l", "common", "loglevel", "HRH_LOGLEVEL"),
+    ("logfile", "common", "logfile", "HRH_LOGFILE"),
+    ("logformat", "common", "logformat", "HRH_LOGFORMAT"),
+    ("p", "common", "postgres", "HRH_POSTGRES"),
+    ("r", "common", "redis", "HRH_REDIS"),
+    ("compat", "common", "compat", "HRH_COMPAT"),
+    ("c", "searches", "cap", "HRH_CAP"),
+    ("svb", "vectors", "bagging", "HRH_BAGGING"),
+    ("svbs", "vectors", "bagsize", "HRH_BAGSIZE"),
+    ("svsh", "vectors", "stopheadwords", "HRH_STOPHEADWORDS"),
+    ("svsi", "vectors", "stopinflected", "HRH_STOPINFLECTED"),
+    ("svc", "vectors", "corpus", "HRH_CORPUS"),
+    ("wsh", "websockets", "host", "HRH_WSHOST"),
+    ("wsp", "websockets", "port", "HRH_WSPORT"),
+    ("wsf", "websockets", "failthreshold", "HRH_WSFAILTHRESHOLD"),
+    ("wss", "websockets", "save", "HRH_WSSAVE"),
+    ("wsv", "websockets", "schema", "HRH_WSSCHEMA"),
+    ("wsx", "websockets", "hits", "HRH_WSHITS"),
+    ("wsa", "websockets", "auth", "HRH_WSAUTH"),
+    ("wsk", "websockets", "secret", "HRH_WSSECRET"),
+    ("wso", "websockets", "origins", "HRH_WSORIGINS"),
+    ("wstc", "websockets", "tlscert", "HRH_WSTLSCERT"),
+    ("wstk", "websockets", "tlskey", "HRH_WSTLSKEY"),
+    ("sq", "serve", "queue", "HRH_QUEUE"),
+    ("sj", "serve", "jobs", "HRH_JOBS"),
+    ("hh", "http", "host", "HRH_HTTPHOST"),
+    ("hp", "http", "port", "HRH_HTTPPORT"),
+    ("hk", "http", "token", "HRH_HTTPTOKEN"),
+    ("hkn", "http", "notoken", "HRH_HTTPNOTOKEN"),
+    ("mh", "metrics", "host", "HRH_METRICSHOST"),
+    ("mp", "metrics", "port", "HRH_METRICSPORT"),
+    ("mf", "metrics", "file", "HRH_METRICSFILE"),
+    ("bn", "bench", "runs", "HRH_BENCHRUNS"),
+    ("bs", "bench", "searches", "HRH_BENCHSEARCHES"),
+];
+
+static CONFIGENV: &str = "HRH_CONFIG";
+
+// a [common.postgres] or [common.redis] table is turned into the JSON that --p and --r take
+static PGKEYS: [(&str, &str); 7] = [("host", "Host"), ("port", "Port"), ("user", "User"), ("password", "Pass"),
+    ("dbname", "DBName"), ("sslmode", "SSLMode"), ("sslrootcert", "SSLRootCert")];
+static RSKEYS: [(&str, &str); 5] = [("addr", "Addr"), ("password", "Password"), ("db", "DB"), ("user", "User"), ("tls", "TLS")];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HelperMode {
+    Version,
+    Grab,
+    Vectors,
+    Websocket,
+    Serve,
+    Http,
+    Compare,
+    Bench,
+}
+
+#[derive(Clone)]
+pub struct HelperConfig {
+    // the command line plus whatever the config file said, keyed by flag name
+    pub mode: HelperMode,
+    top: ArgMatches<'static>,
+    sub: Option<ArgMatches<'static>>,
+    file: HashMap<String, String>,
+}
+
+impl HelperConfig {
+    pub fn load(cli: ArgMatches<'static>) -> HelperResult<HelperConfig> {
+        // "grab --t 3" and "--t 3 grab" are the same thing; with no subcommand the legacy flags pick the mode
+        let (mode, sub) = match cli.subcommand() {
+            ("grab", Some(m)) => (HelperMode::Grab, Some(m.clone())),
+            ("vectors", Some(m)) => (HelperMode::Vectors, Some(m.clone())),
+            ("websocket", Some(m)) => (HelperMode::Websocket, Some(m.clone())),
+            ("serve", Some(m)) => (HelperMode::Serve, Some(m.clone())),
+            ("http", Some(m)) => (HelperMode::Http, Some(m.clone())),
+            ("compare", Some(m)) => (HelperMode::Compare, Some(m.clone())),
+            ("bench", Some(m)) => (HelperMode::Bench, Some(m.clone())),
+            _ if cli.is_present("v") => (HelperMode::Version, None),
+            _ if cli.is_present("ws") => (HelperMode::Websocket, None),
+            _ if cli.is_present("sv") => (HelperMode::Vectors, None),
+            _ => (HelperMode::Grab, None),
+        };
+        if sub.is_some() && ["sv", "ws", "v"].iter().any(|f| cli.is_present(f)) {
+            return Err(HelperError::Config("--sv, --ws and --v cannot be combined with a subcommand".to_string()))
+        }
+
+        let mut cfg = HelperConfig { mode, top: cli, sub, file: HashMap::new() };
+        let path: Option<String> = match cfg.given("config") {
+            Some(p) => Some(p),
+            None => env::var(CONFIGENV).ok().filter(|p| !p.is_empty()),
+        };
+        if let Some(p) = path {
+            cfg.file = cf_readfile(&p)?;
+        }
+        Ok(cfg)
+    }
+
+    fn given(&self, flag: &str) -> Option<String> {
+        // only what was typed on the command line, and not the clap defaults
+        self.sub.iter()
+            .chain(std::iter::once(&self.top))
+            .find(|m| m.occurrences_of(flag) > 0)
+            .and_then(|m| m.value_of(flag))
+            .map(|v| v.to_string())
+    }
+
+    pub fn value(&self, flag: &str) -> Option<String> {
+        // the winning value for a flag that has a row in SETTINGS (and what clap says for any other flag)
+        if let Some(v) = self.given(flag) {
+            return Some(v)
+        }
+        if let Some((_, _, _, var)) = SETTINGS.iter().find(|s| s.0 == flag) {
+            // HRH_CAP= is unset, as it is for HRH_CONFIG, and not an empty value that wins over the file
+            if let Some(v) = env::var(var).ok().filter(|v| !v.is_empty()) {
+                return Some(v)
+            }
+        }
+        if let Some(v) = self.file.get(flag) {
+            return Some(v.clone())
+        }
+        self.sub.as_ref()
+            .and_then(|m| m.value_of(flag))
+            .or_else(|| self.top.value_of(flag))
+            .map(|v| v.to_string())
+    }
+
+    pub fn text(&self, flag: &str) -> String {
+        self.value(flag).unwrap_or_default()
+    }
+
+    pub fn parse<T: FromStr>(&self, flag: &str) -> HelperResult<T> {
+        // "--t x" (or "workers = 'x'", or HRH_WORKERS=x) is a config error and not a panic
+        let v = self.text(flag);
+        v.parse().map_err(|_| HelperError::Config(format!("--{} does not accept '{}'", flag, v)))
+    }
+}
+
+fn cf_readfile(path: &str) -> HelperResult<HashMap<String, String>> {
+    // [common]
+    // workers = 8
+    // loglevel = 1
+    //
+    // [common.postgres]
+    // host = "/var/run/postgresql"
+    // user = "hippa_wr"
+    // password = "..."
+    // dbname = "hipparchiaDB"
+    //
+    // [websockets]
+    // port = 5010
+    // origins = ["https://hipparchia.example.org"]
+    let text = fs::read_to_string(path).map_err(|e| HelperError::Config(format!("cannot read the config file {}: {}", path, e)))?;
+    let parsed: Value = text.parse().map_err(|e| HelperError::Config(format!("cannot parse the config file {}: {}", path, e)))?;
+
+    let sections = match parsed.as_table() {
+        Some(t) => t,
+        None => return Ok(HashMap::new()),
+    };
+
+    let mut found: HashMap<String, String> = HashMap::new();
+    for (section, entries) in sections {
+        let entries = entries.as_table()
+            .ok_or_else(|| HelperError::Config(format!("{}: '{}' should be a [section]", path, section)))?;
+        for (key, v) in entries {
+            // a typo should not quietly fall back to the default
+            let (flag, _, _, _) = SETTINGS.iter()
+                .find(|s| s.1 == section && s.2 == key)
+                .ok_or_else(|| HelperError::Config(format!("{}: unknown setting [{}] {}", path, section, key)))?;
+            let v = cf_flatten(flag, v).map_err(|e| HelperError::Config(format!("{}: [{}] {}: {}", path, section, key, e)))?;
+            found.insert(flag.to_string(), v);
+        }
+    }
+    Ok(found)
+}
+
+fn cf_flatten(flag: &str, v: &Value) -> Result<String, String> {
+    // turn a TOML value into the string that the flag would have been handed
+    match (flag, v) {
+        (_, Value::String(s)) => Ok(s.clone()),
+        (_, Value::Integer(i)) => Ok(i.to_string()),
+        ("wss", Value::Boolean(b)) | ("hkn", Value::Boolean(b)) => Ok(if *b { "1" } else { "0" }.to_string()),
+        ("wso", Value::Array(a)) => cf_strings(a).map(|s| s.join(",")),
+        ("svsh", Value::Array(a)) | ("svsi", Value::Array(a)) => cf_strings(a).map(|s| s.join(" ")),
+        ("p", Value::Table(t)) => cf_loginjson(t, &PGKEYS),
+        ("r", Value::Table(t)) => cf_loginjson(t, &RSKEYS),
+        (_, other) => Err(format!("cannot use a {} here", other.type_str())),
+    }
+}
+
+fn cf_strings(a: &[Value]) -> Result<Vec<String>, String> {
+    a.iter()
+        .map(|v| v.as_str().map(|s| s.to_string()).ok_or_else(|| "expected a list of strings".to_string()))
+        .collect()
+}
+
+fn cf_loginjson(t: &toml::value::Table, keys: &[(&str, &str)]) -> Result<String, String> {
+    // {host = "localhost", port = 5432, ...} --> {"Host": "localhost", "Port": 5432, ...}
+    let mut j = JsonValue::new_object();
+    for (k, v) in t {
+        let jk = match keys.iter().find(|(tk, _)| tk == k) {
+            Some((_, jk)) => jk,
+            None => return Err(format!("unknown key '{}': use {}", k, keys.iter().map(|(tk, _)| *tk).collect::<Vec<&str>>().join(", "))),
+        };
+        j[*jk] = match v {
+            Value::String(s) => s.as_str().into(),
+            Value::Integer(i) => (*i).into(),
+            Value::Boolean(b) => (*b).into(),
+            other => return Err(format!("'{}' cannot be a {}", k, other.type_str())),
+        };
+    }
+    Ok(j.dump())
+}
@@ -0,0 +1,148 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::ops::{Deref, DerefMut};
+
+use log::warn;
+use r2d2::Pool;
+
+use crate::dbfunctions::*;
+use crate::theerrors::*;
+use crate::thepools::*;
+use crate::theschema::*;
+use crate::thestructs::*;
+
+// where the bagging code gets its author lines, morphology and headword counts
+// (and where the grabber runs its work items)
+//  postgres (PgCorpus, below) is the HipparchiaServer database; FileCorpus (thefiles.rs) reads the same tables
+//  from CSV or JSONL dumps, so that bags can be built with no database at all (--svc)
+//
+// NB: a work item is SQL, so only postgres can search; the --svc files are for bags built from --svdb
+
+pub trait CorpusSource: Send + Sync {
+    // a handle for one thread: for postgres this checks a connection out of the pool until it is dropped
+    fn open(&self) -> HelperResult<Box<dyn CorpusConn>>;
+    // for the log and error messages
+    fn describe(&self) -> String;
+    // can it run the SQL in a work item? (bench asks before it starts instead of failing halfway through)
+    fn searches(&self) -> bool;
+}
+
+pub trait CorpusConn {
+    // lines first through last (by index) of an author table: "vectors --svdb lt0448 --svs 1 --sve 250"
+    fn lines(&mut self, table: &str, first: i32, last: i32) -> HelperResult<Vec<DBLine>>;
+    // the lines that one work item (see db_workitem()) finds
+    fn workitem(&mut self, temptable: &str, query: &str, data: &str) -> HelperResult<Vec<DBLine>>;
+    // the rows of {lang}_morphology whose observed_form is one of words
+    fn morphology(&mut self, words: &[String], lang: &str) -> HelperResult<Vec<DbMorphology>>;
+    // the rows of dictionary_headword_wordcounts for these headwords (the missing ones are just missing)
+    fn headwordcounts(&mut self, headwords: &[String]) -> HelperResult<Vec<WeightedHeadword>>;
+    // what the tables look like: see sc_detect()
+    fn schema(&mut self, linetable: Option<&str>) -> HelperResult<DbSchema>;
+    // the end of a run: nothing may be left behind for whoever uses the connection next
+    fn finish(&mut self) -> HelperResult<()>;
+}
+
+pub struct CorpusGuard {
+    // a CorpusConn that is always finish()ed: by finish() itself when the run went well, by drop() when a ? or a
+    // panic cut it short (a pooled postgres connection lives as long as a daemon does, and so would the temp tables)
+    conn: Box<dyn CorpusConn>,
+    finished: bool,
+}
+
+impl CorpusGuard {
+    pub fn open(source: &dyn CorpusSource) -> HelperResult<CorpusGuard> {
+        Ok(CorpusGuard { conn: source.open()?, finished: false })
+    }
+
+    pub fn finish(mut self) -> HelperResult<()> {
+        // the normal ending: here a failure to clean up is an error like any other
+        self.finished = true;
+        self.conn.finish()
+    }
+}
+
+impl Deref for CorpusGuard {
+    type Target = dyn CorpusConn;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.conn
+    }
+}
+
+impl DerefMut for CorpusGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.conn
+    }
+}
+
+impl Drop for CorpusGuard {
+    fn drop(&mut self) {
+        // we are already on the way out with some other error: this one can only be logged
+        if !self.finished {
+            if let Err(e) = self.conn.finish() {
+                warn!("could not clean up after an interrupted run: {}", e);
+            }
+        }
+    }
+}
+
+pub struct PgCorpus {
+    pool: Pool<PgManager>,
+    name: String,
+}
+
+impl PgCorpus {
+    pub fn new(pool: Pool<PgManager>, name: &str) -> PgCorpus {
+        // shares the pool that DbPools hands to the searches
+        PgCorpus { pool, name: name.to_string() }
+    }
+}
+
+impl CorpusSource for PgCorpus {
+    fn open(&self) -> HelperResult<Box<dyn CorpusConn>> {
+        Ok(Box::new(PgCorpusConn { pg: pg_checkout(&self.pool, &self.name)? }))
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn searches(&self) -> bool {
+        true
+    }
+}
+
+struct PgCorpusConn {
+    pg: PgConnection,
+}
+
+impl CorpusConn for PgCorpusConn {
+    fn lines(&mut self, table: &str, first: i32, last: i32) -> HelperResult<Vec<DBLine>> {
+        db_directfetch(table, first, last, &mut self.pg)
+    }
+
+    fn workitem(&mut self, temptable: &str, query: &str, data: &str) -> HelperResult<Vec<DBLine>> {
+        db_workitemlines(temptable, query, data, &mut self.pg)
+    }
+
+    fn morphology(&mut self, words: &[String], lang: &str) -> HelperResult<Vec<DbMorphology>> {
+        db_morphology(words, lang, &mut self.pg)
+    }
+
+    fn headwordcounts(&mut self, headwords: &[String]) -> HelperResult<Vec<WeightedHeadword>> {
+        db_headwordcounts(headwords, &mut self.pg)
+    }
+
+    fn schema(&mut self, linetable: Option<&str>) -> HelperResult<DbSchema> {
+        sc_detect(&mut self.pg, linetable)
+    }
+
+    fn finish(&mut self) -> HelperResult<()> {
+        // the connection goes back into the pool: do not leave the temp tables behind for the next job
+        self.pg.batch_execute("DISCARD TEMP")?;
+        Ok(())
+    }
+}
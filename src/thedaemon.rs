@@ -0,0 +1,249 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use humantime::format_duration;
+use json::JsonValue;
+use log::{info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::theconfig::*;
+use crate::theerrors::*;
+use crate::thegrabber::*;
+use crate::thelogging::*;
+use crate::thepools::*;
+use crate::theschema::*;
+use crate::thestorage::*;
+use crate::thevectors::*;
+
+// how long one BLPOP waits before looking to see if we have been asked to stop (seconds)
+static DMWAIT: usize = 1;
+// how long to leave redis alone after it could not be reached even with retries
+static DMPAUSE: Duration = Duration::from_secs(2);
+
+// what a job may override; everything else comes from the config/env/flags the daemon started with
+static GRABOPTIONS: [&str; 1] = ["cap"];
+static VECTOROPTIONS: [&str; 7] = ["bagging", "bagsize", "stopheadwords", "stopinflected", "table", "start", "end"];
+
+pub struct DmJob {
+    // {"type": "grab"|"vectors", "key": "...", "options": {...}}
+    pub kind: String,
+    pub key: String,
+    pub options: JsonValue,
+}
+
+pub fn daemon(cfg: HelperConfig, workers: i32, pools: &DbPools, schema: DbSchema) -> HelperResult<()> {
+    // the SERVE mode keeps one process (and its warm pools) up instead of forking a helper per search
+    //
+    // [a] each of --sj job slots waits on the --sq redis list (BLPOP)
+    // [b] a job is {"type": "grab"|"vectors", "key": "...", "options": {...}}
+    // [c] it is run by the same grabber() or vector_prep() that the one-shot modes use
+    // [d] "<key>_done" gets the result key, or the JSON error report if the job failed
+    //
+    // SIGTERM or SIGINT: stop taking jobs, finish the ones that are running, exit 0
+    //
+    let queue = cfg.text("sq");
+    let slots: i32 = cfg.parse("sj")?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    for s in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(s, stop.clone())
+            .map_err(|e| HelperError::Config(format!("cannot listen for signals: {}", e)))?;
+    }
+
+    info!("serving jobs from the redis list '{}' with {} job slots of {} workers", queue, slots.max(1), workers);
+
+    let schema = Arc::new(schema);
+    let mut handles: Vec<thread::JoinHandle<_>> = Vec::new();
+    for i in 0..slots.max(1) {
+        let q = queue.clone();
+        let c = cfg.clone();
+        let p = pools.clone();
+        let s = schema.clone();
+        let x = stop.clone();
+        let h = thread::Builder::new().name(format!("serve-{}", i)).spawn( move || {
+            dm_jobslot(&q, &c, workers, &p, &s, &x)
+        });
+        match h {
+            Ok(h) => handles.push(h),
+            Err(e) => {
+                // the slots that did start are let go before we give up
+                stop.store(true, Ordering::SeqCst);
+                for h in handles {
+                    let _ = h.join();
+                }
+                return Err(HelperError::Config(format!("could not start job slot {} of {}: {}", i + 1, slots.max(1), e)))
+            }
+        }
+    }
+
+    for h in handles {
+        let _ = h.join();
+    }
+    info!("all job slots are idle: shutting down");
+    Ok(())
+}
+
+fn dm_jobslot(queue: &str, cfg: &HelperConfig, workers: i32, pools: &DbPools, schema: &DbSchema, stop: &AtomicBool) {
+    // one job at a time, for as long as the daemon is up
+    let mut redisconn = RsConn::new(pools);
+    while !stop.load(Ordering::SeqCst) {
+        let j = match rs_blpop(queue, DMWAIT, &mut redisconn) {
+            Ok(Some(j)) => j,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("cannot read the job queue: {}", e);
+                thread::sleep(DMPAUSE);
+                continue
+            }
+        };
+
+        let job = match dm_parsejob(&j) {
+            Ok(job) => job,
+            Err(e) => {
+                warn!("dropping a job that cannot be read: {}", e);
+                continue
+            }
+        };
+
+        let done = dm_execute(&job, cfg, workers, pools, schema).unwrap_or_else(|report| report);
+        let thiskey = format!("{}_done", job.key);
+        if let Err(e) = rs_set_str(&thiskey, &done, &mut redisconn) {
+            warn!("could not record the outcome in {}: {}", thiskey, e);
+        }
+    }
+}
+
+pub fn dm_execute(job: &DmJob, cfg: &HelperConfig, workers: i32, pools: &DbPools, schema: &DbSchema) -> Result<String, String> {
+    // run one job to the end: Ok(resultkey), or Err(the JSON error report)
+    lg_setkey(&job.key);
+    info!("starting a {} job", job.kind);
+    let start = Instant::now();
+
+    // a bug in one job should not take the other slots (and everyone's searches) down with it
+    let outcome = er_catchpanic(|| dm_runjob(job, cfg, workers, pools, schema));
+    let result = match outcome {
+        Ok(Ok(resultkey)) => {
+            info!("{} job finished [{}]", job.kind, format_duration(start.elapsed()));
+            Ok(resultkey)
+        }
+        Ok(Err(e)) => {
+            warn!("{} job failed: {}", job.kind, e);
+            Err(e.report())
+        }
+        Err(what) => {
+            warn!("{} job panicked: {}", job.kind, what);
+            Err(er_report("panic", 101, &format!("the {} job panicked: {}", job.kind, what)))
+        }
+    };
+    lg_clearkey();
+    result
+}
+
+fn dm_parsejob(j: &str) -> HelperResult<DmJob> {
+    // {"type": "vectors", "key": "a1b2c3d4", "options": {"bagging": "flat", "bagsize": 2}}
+    let parsed = json::parse(j).map_err(|e| HelperError::WorkItem(format!("job is not JSON ({}): {}", e, j)))?;
+    let text = |k: &str| -> HelperResult<String> {
+        match parsed[k].as_str() {
+            Some(v) if !v.is_empty() => Ok(v.to_string()),
+            _ => Err(HelperError::WorkItem(format!("no {} in {}", k, j))),
+        }
+    };
+    let options = match &parsed["options"] {
+        JsonValue::Null => JsonValue::new_object(),
+        o if o.is_object() => o.clone(),
+        _ => return Err(HelperError::WorkItem(format!("options should be an object in {}", j))),
+    };
+    Ok(DmJob { kind: text("type")?, key: text("key")?, options })
+}
+
+pub fn dm_validate(job: &DmJob) -> HelperResult<()> {
+    // is this a job that dm_runjob() knows how to do?
+    match job.kind.as_str() {
+        "grab" => dm_checkoptions(job, &GRABOPTIONS),
+        "vectors" => {
+            dm_checkoptions(job, &VECTOROPTIONS)?;
+            match dm_text(job, "table", " ")? {
+                Some(t) => dm_tablename(&t),
+                None => Ok(()),
+            }
+        }
+        other => Err(HelperError::UnsupportedMode(format!("unknown job type '{}': use grab or vectors", other))),
+    }
+}
+
+fn dm_runjob(job: &DmJob, cfg: &HelperConfig, workers: i32, pools: &DbPools, schema: &DbSchema) -> HelperResult<String> {
+    dm_validate(job)?;
+    match job.kind.as_str() {
+        "grab" => {
+            let cap: i32 = dm_number(job, "cap")?.unwrap_or(cfg.parse("c")?);
+            grabber(cap, job.key.clone(), workers, pools)
+        }
+        "vectors" => {
+            schema.requiremorphology()?;
+            let b: String = dm_text(job, "bagging", " ")?.unwrap_or_else(|| cfg.text("svb"));
+            let bs: i32 = dm_number(job, "bagsize")?.unwrap_or(cfg.parse("svbs")?);
+            let stops = (
+                dm_text(job, "stopheadwords", " ")?.or_else(|| cfg.value("svsh")),
+                dm_text(job, "stopinflected", " ")?.or_else(|| cfg.value("svsi")),
+            );
+            // {"table": "lt0448", "start": 1, "end": 250} reads those lines instead of the work items, as --svdb does
+            match dm_text(job, "table", " ")? {
+                Some(t) => {
+                    // dm_validate() has checked the name
+                    let s = dm_number(job, "start")?.unwrap_or(1);
+                    let e = dm_number(job, "end")?.unwrap_or(i32::MAX);
+                    BagPipeline::standard(&b, bs, &t, s, e, stops)?.run(&job.key, pools)
+                }
+                None if !job.options["start"].is_null() || !job.options["end"].is_null() => {
+                    Err(HelperError::WorkItem("a vectors job with 'start' or 'end' also needs a 'table'".to_string()))
+                }
+                None => vector_prep(&job.key, &b, workers, bs, &cfg.text("svdb"), cfg.parse("svs")?, cfg.parse("sve")?, stops, pools),
+            }
+        }
+        _ => unreachable!("dm_validate() let through a job type that dm_runjob() does not know"),
+    }
+}
+
+fn dm_checkoptions(job: &DmJob, allowed: &[&str]) -> HelperResult<()> {
+    // a misspelled option should not quietly run with the default
+    match job.options.entries().find(|(k, _)| !allowed.contains(k)) {
+        Some((k, _)) => Err(HelperError::WorkItem(format!("a {} job has no option '{}': use {}", job.kind, k, allowed.join(", ")))),
+        None => Ok(()),
+    }
+}
+
+fn dm_tablename(t: &str) -> HelperResult<()> {
+    // the name goes into the SQL as it is: "lt0448" or "gr0012", and nothing that could be anything else
+    match !t.is_empty() && t.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        true => Ok(()),
+        false => Err(HelperError::WorkItem(format!("'{}' is not a table name", t))),
+    }
+}
+
+fn dm_number(job: &DmJob, k: &str) -> HelperResult<Option<i32>> {
+    match &job.options[k] {
+        JsonValue::Null => Ok(None),
+        v => v.as_i32().map(Some).ok_or_else(|| HelperError::WorkItem(format!("option '{}' should be a number, not {}", k, v.dump()))),
+    }
+}
+
+fn dm_text(job: &DmJob, k: &str, joiner: &str) -> HelperResult<Option<String>> {
+    // a string, or a list of strings (e.g., the stoplists)
+    match &job.options[k] {
+        JsonValue::Null => Ok(None),
+        v if v.is_array() => {
+            let words: Option<Vec<&str>> = v.members().map(|w| w.as_str()).collect();
+            words.map(|w| Some(w.join(joiner)))
+                .ok_or_else(|| HelperError::WorkItem(format!("option '{}' should be a list of strings, not {}", k, v.dump())))
+        }
+        v => v.as_str().map(|s| Some(s.to_string()))
+            .ok_or_else(|| HelperError::WorkItem(format!("option '{}' should be a string, not {}", k, v.dump()))),
+    }
+}
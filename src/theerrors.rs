@@ -0,0 +1,155 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::any::Any;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use json::JsonValue;
+use log::error;
+
+use crate::themetrics::*;
+use crate::thestorage::*;
+
+// HipparchiaServer reads our stdout for a result key; when there is no key it can look at the exit status
+// and at the one-line JSON report on stderr instead:
+//      0   ok
+//      2   config      - bad flags, unreadable settings, a postgres/redis login string that does not parse
+//      3   postgres    - could not connect, or a query failed
+//      4   redis       - could not connect, or a command failed (after retries, where a retry is safe)
+//      5   workitem    - a query pulled off the redis work pile was not what we expected
+//      6   unsupported - a mode or option that this helper does not (yet) do
+//      7   schema      - the database (or the --svc files) is missing tables or columns that we need (see theschema.rs)
+//      101 panic       - a bug: the report carries the panic message and where it happened
+
+#[derive(Debug)]
+pub enum HelperError {
+    Config(String),
+    Postgres(String),
+    Redis(String),
+    WorkItem(String),
+    UnsupportedMode(String),
+    Schema(String),
+}
+
+pub type HelperResult<T> = Result<T, HelperError>;
+
+impl HelperError {
+    pub fn exitcode(&self) -> i32 {
+        match self {
+            HelperError::Config(_) => 2,
+            HelperError::Postgres(_) => 3,
+            HelperError::Redis(_) => 4,
+            HelperError::WorkItem(_) => 5,
+            HelperError::UnsupportedMode(_) => 6,
+            HelperError::Schema(_) => 7,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HelperError::Config(_) => "config",
+            HelperError::Postgres(_) => "postgres",
+            HelperError::Redis(_) => "redis",
+            HelperError::WorkItem(_) => "workitem",
+            HelperError::UnsupportedMode(_) => "unsupported",
+            HelperError::Schema(_) => "schema",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            HelperError::Config(m)
+            | HelperError::Postgres(m)
+            | HelperError::Redis(m)
+            | HelperError::WorkItem(m)
+            | HelperError::UnsupportedMode(m)
+            | HelperError::Schema(m) => m,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        // {"Error": "redis", "ExitCode": 4, "Message": "redis is unreachable: ..."}
+        er_report(self.kind(), self.exitcode(), self.message())
+    }
+}
+
+impl fmt::Display for HelperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind(), self.message())
+    }
+}
+
+impl From<RsError> for HelperError {
+    fn from(e: RsError) -> HelperError {
+        HelperError::Redis(e.to_string())
+    }
+}
+
+impl From<postgres::Error> for HelperError {
+    fn from(e: postgres::Error) -> HelperError {
+        // every failed query passes through here on its way up, which makes this the place to count them
+        mt_count(MtCounter::PostgresErrors);
+        HelperError::Postgres(e.to_string())
+    }
+}
+
+pub fn er_report(kind: &str, code: i32, message: &str) -> String {
+    let mut data = JsonValue::new_object();
+    data["Error"] = kind.into();
+    data["ExitCode"] = code.into();
+    data["Message"] = message.into();
+    data.dump()
+}
+
+thread_local! {
+    // is this thread running inside er_catchpanic()? then a panic is not the end of the process
+    static ERCATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn er_panicreports() {
+    // a panic (in any thread) still gets a machine-readable line on stderr before the process dies with 101
+    //  one that er_catchpanic() is going to catch only goes to the log: the caller reports it
+    panic::set_hook(Box::new(|info| {
+        let wherefrom: String = match info.location() {
+            Some(l) => format!(" at {}:{}", l.file(), l.line()),
+            None => "".to_string(),
+        };
+        let what = format!("{}{}", er_panicmessage(info.payload()), wherefrom);
+        match er_catching() {
+            true => error!("panic: {}", what),
+            false => eprintln!("{}", er_report("panic", 101, &what)),
+        }
+    }));
+}
+
+pub fn er_catchpanic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    // run f and turn a panic into Err(its message) instead of a dead thread
+    let before = ERCATCHING.with(|c| c.replace(true));
+    let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+    ERCATCHING.with(|c| c.set(before));
+    outcome.map_err(|p| er_panicmessage(p.as_ref()))
+}
+
+pub fn er_catching() -> bool {
+    ERCATCHING.with(|c| c.get())
+}
+
+pub fn er_setcatching(on: bool) {
+    // for the threads that a caught job spawns: their panics come back to it through join()
+    ERCATCHING.with(|c| c.set(on));
+}
+
+fn er_panicmessage(payload: &(dyn Any + Send)) -> String {
+    // panic!("...") carries a &str, panic!("{}", x) a String
+    match payload.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
+}
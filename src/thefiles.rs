@@ -0,0 +1,301 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use json::JsonValue;
+use regex::Regex;
+
+use crate::thecorpus::*;
+use crate::theerrors::*;
+use crate::theschema::*;
+use crate::thestructs::*;
+
+// a corpus exported from HipparchiaServer's database, one file per table:
+//
+//  lt0448.csv (or lt0448.jsonl), ..., latin_morphology.csv, greek_morphology.csv, dictionary_headword_wordcounts.csv
+//
+// CSV is what "\copy lt0448 TO 'lt0448.csv' CSV HEADER" writes: a header row with the column names, then the rows
+// JSONL is one {"column": value, ...} object per line
+// only the columns that the bagging code reads are needed (see theschema.rs); any others are ignored
+
+// looked for in this order
+static FLEXTENSIONS: [&str; 2] = ["jsonl", "csv"];
+
+#[derive(Clone)]
+pub struct FileCorpus {
+    dir: PathBuf,
+}
+
+impl FileCorpus {
+    pub fn new(dir: &str) -> HelperResult<FileCorpus> {
+        let d = PathBuf::from(dir);
+        if !d.is_dir() {
+            return Err(HelperError::Config(format!("the corpus directory '{}' is not a directory", dir)))
+        }
+        Ok(FileCorpus { dir: d })
+    }
+
+    fn find(&self, table: &str) -> Option<PathBuf> {
+        FLEXTENSIONS.iter()
+            .map(|x| self.dir.join(format!("{}.{}", table, x)))
+            .find(|p| p.is_file())
+    }
+
+    fn require(&self, table: &str) -> HelperResult<PathBuf> {
+        self.find(table).ok_or_else(|| HelperError::Schema(format!("there is no {}.jsonl or {}.csv in {}", table, table, self.dir.display())))
+    }
+
+    fn firstlinetable(&self) -> HelperResult<Option<String>> {
+        // any one author table will do for sc_detect()'s purposes: the first one, as in postgres
+        let authortable = Regex::new(r"^([a-z]{2}[0-9]{4})\.(jsonl|csv)$").unwrap();
+        let entries = fs::read_dir(&self.dir)
+            .map_err(|e| HelperError::Config(format!("could not read {}: {}", self.dir.display(), e)))?;
+        let mut tables: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| authortable.captures(&e.file_name().to_string_lossy()).map(|c| c[1].to_string()))
+            .collect();
+        tables.sort();
+        Ok(tables.into_iter().next())
+    }
+}
+
+impl CorpusSource for FileCorpus {
+    fn open(&self) -> HelperResult<Box<dyn CorpusConn>> {
+        // nothing is held open between calls: every call reads its file again
+        Ok(Box::new(self.clone()))
+    }
+
+    fn describe(&self) -> String {
+        format!("the files in {}", self.dir.display())
+    }
+
+    fn searches(&self) -> bool {
+        false
+    }
+}
+
+impl CorpusConn for FileCorpus {
+    fn lines(&mut self, table: &str, first: i32, last: i32) -> HelperResult<Vec<DBLine>> {
+        let mut lines: Vec<DBLine> = Vec::new();
+        fl_scan(&self.require(table)?, |row| {
+            let l = sc_fileline(row)?;
+            if l.idx >= first && l.idx <= last {
+                lines.push(l);
+            }
+            Ok(())
+        })?;
+        Ok(lines)
+    }
+
+    fn workitem(&mut self, _temptable: &str, _query: &str, _data: &str) -> HelperResult<Vec<DBLine>> {
+        Err(HelperError::UnsupportedMode(format!("{} cannot run the SQL in a work item: use --svdb, --svs and --sve instead", self.describe())))
+    }
+
+    fn morphology(&mut self, words: &[String], lang: &str) -> HelperResult<Vec<DbMorphology>> {
+        let wanted: HashSet<&str> = words.iter().map(|w| w.as_str()).collect();
+        let mut found: Vec<DbMorphology> = Vec::new();
+        fl_scan(&self.require(&format!("{}_morphology", lang))?, |row| {
+            let obs = fl_get(row, "observed_form")?;
+            if wanted.contains(obs.as_str()) {
+                let rpo = fl_get(row, "related_headwords")?;
+                found.push(DbMorphology {
+                    obs,
+                    xrf: fl_get(row, "xrefs")?,
+                    pxr: fl_get(row, "prefixrefs")?,
+                    upo: rpo.split_whitespace().map(|s| s.to_string()).collect(),
+                    rpo,
+                });
+            }
+            Ok(())
+        })?;
+        Ok(found)
+    }
+
+    fn headwordcounts(&mut self, headwords: &[String]) -> HelperResult<Vec<WeightedHeadword>> {
+        let wanted: HashSet<&str> = headwords.iter().map(|w| w.as_str()).collect();
+        let mut found: Vec<WeightedHeadword> = Vec::new();
+        fl_scan(&self.require("dictionary_headword_wordcounts")?, |row| {
+            let wd = fl_get(row, "entry_name")?;
+            if wanted.contains(wd.as_str()) {
+                let ct = fl_get(row, "total_count")?;
+                let ct = ct.trim().parse().map_err(|_| format!("'{}' is not a count", ct))?;
+                found.push(WeightedHeadword { wd, ct });
+            }
+            Ok(())
+        })?;
+        Ok(found)
+    }
+
+    fn schema(&mut self, linetable: Option<&str>) -> HelperResult<DbSchema> {
+        // the columns are the CSV header (or the keys of the first JSON object)
+        let linetable: Option<String> = match linetable {
+            Some(t) => Some(t.to_string()),
+            None => self.firstlinetable()?,
+        };
+        let mut columns: HashMap<String, HashSet<String>> = HashMap::new();
+        for t in sc_tables(&linetable) {
+            if let Some(p) = self.find(&t) {
+                columns.insert(t, fl_columns(&p)?);
+            }
+        }
+        Ok(sc_fromcolumns(linetable, columns))
+    }
+
+    fn finish(&mut self) -> HelperResult<()> {
+        Ok(())
+    }
+}
+
+fn fl_get(row: &HashMap<String, String>, column: &str) -> Result<String, String> {
+    row.get(column).cloned().ok_or_else(|| format!("no '{}'", column))
+}
+
+fn fl_columns(path: &Path) -> HelperResult<HashSet<String>> {
+    // [a] the CSV header or [b] the first JSON object's keys
+    let mut columns: HashSet<String> = HashSet::new();
+    let mut records = FlRecords::open(path)?;
+    if let Some((n, r)) = records.next().transpose()? {
+        if records.csv {
+            columns.extend(fl_csvfields(&r).unwrap_or_default());
+        } else {
+            columns.extend(fl_jsonrow(&r).map_err(|e| fl_error(path, n, &e))?.into_keys());
+        }
+    }
+    Ok(columns)
+}
+
+fn fl_scan<F>(path: &Path, mut f: F) -> HelperResult<()>
+where
+    F: FnMut(&HashMap<String, String>) -> Result<(), String>,
+{
+    // every row of a table as column --> value; f's complaints come back with the file and line attached
+    let mut records = FlRecords::open(path)?;
+    let header: Option<Vec<String>> = match records.csv {
+        true => records.next().transpose()?.map(|(_, h)| fl_csvfields(&h).unwrap_or_default()),
+        false => None,
+    };
+    while let Some((n, r)) = records.next().transpose()? {
+        let row: HashMap<String, String> = match &header {
+            Some(h) => {
+                let fields = fl_csvfields(&r).unwrap_or_default();
+                if fields.len() != h.len() {
+                    return Err(fl_error(path, n, &format!("{} fields where the header has {}", fields.len(), h.len())))
+                }
+                h.iter().cloned().zip(fields).collect()
+            }
+            None => fl_jsonrow(&r).map_err(|e| fl_error(path, n, &e))?,
+        };
+        f(&row).map_err(|e| fl_error(path, n, &e))?;
+    }
+    Ok(())
+}
+
+fn fl_error(path: &Path, n: usize, e: &str) -> HelperError {
+    HelperError::Schema(format!("{}:{}: {}", path.display(), n, e))
+}
+
+fn fl_jsonrow(r: &str) -> Result<HashMap<String, String>, String> {
+    // {"index": 1, "wkuniversalid": "lt0448w001", ...}: numbers (and anything else) become text, null becomes ""
+    let parsed = json::parse(r).map_err(|e| format!("not JSON ({})", e))?;
+    if !parsed.is_object() {
+        return Err("not a JSON object".to_string())
+    }
+    Ok(parsed.entries()
+        .map(|(k, v)| {
+            let v = match v {
+                JsonValue::Null => String::new(),
+                JsonValue::String(_) | JsonValue::Short(_) => v.as_str().unwrap_or_default().to_string(),
+                _ => v.dump(),
+            };
+            (k.to_string(), v)
+        })
+        .collect())
+}
+
+fn fl_csvfields(record: &str) -> Option<Vec<String>> {
+    // one CSV record: "quoted, fields" with "" for a quote; None if a quoted field has not ended yet
+    let mut fields: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            (_, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return None
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+struct FlRecords {
+    // the records of a file with their (first) line numbers: a CSV record can run over several lines
+    lines: std::io::Lines<BufReader<File>>,
+    path: PathBuf,
+    csv: bool,
+    n: usize,
+}
+
+impl FlRecords {
+    fn open(path: &Path) -> HelperResult<FlRecords> {
+        let f = File::open(path).map_err(|e| HelperError::Config(format!("could not read {}: {}", path.display(), e)))?;
+        Ok(FlRecords {
+            lines: BufReader::new(f).lines(),
+            path: path.to_path_buf(),
+            csv: path.extension().is_some_and(|x| x == "csv"),
+            n: 0,
+        })
+    }
+
+    fn readline(&mut self) -> HelperResult<Option<String>> {
+        self.n += 1;
+        match self.lines.next() {
+            None => Ok(None),
+            Some(Ok(l)) => Ok(Some(l.strip_suffix('\r').map(|l| l.to_string()).unwrap_or(l))),
+            Some(Err(e)) => Err(HelperError::Config(format!("could not read {}: {}", self.path.display(), e))),
+        }
+    }
+}
+
+impl Iterator for FlRecords {
+    type Item = HelperResult<(usize, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // blank lines are skipped
+        let mut record = loop {
+            match self.readline() {
+                Ok(Some(l)) if l.trim().is_empty() => continue,
+                Ok(Some(l)) => break l,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        };
+        let start = self.n;
+        while self.csv && fl_csvfields(&record).is_none() {
+            match self.readline() {
+                Ok(Some(l)) => {
+                    record.push('\n');
+                    record.push_str(&l);
+                }
+                Ok(None) => return Some(Err(fl_error(&self.path, start, "a quoted field never ends"))),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok((start, record)))
+    }
+}
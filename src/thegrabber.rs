@@ -3,133 +3,144 @@
 //    License: GNU GENERAL PUBLIC LICENSE 3
 //
 
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 
-use clap::ArgMatches;
-use json::JsonValue;
-use postgres::Error;
+use log::{debug, info, trace};
 use uuid::Uuid;
 
 use crate::dbfunctions::*;
-use crate::helpers::*;
-
-pub fn grabber(cliclone: ArgMatches<'static>, thekey: String, ll: i32, workers: i32, rc: String) -> String {
+use crate::thecompat::*;
+use crate::thecorpus::*;
+use crate::theerrors::*;
+use crate::thelogging::*;
+use crate::themetrics::*;
+use crate::thepools::*;
+use crate::thestorage::*;
+
+pub fn grabber(cap: i32, thekey: String, workers: i32, pools: &DbPools) -> HelperResult<String> {
     // the GRABBER is supposed to be pointedly basic
     //
     // [a] it looks to redis for a pile of SQL queries that were pre-rolled
-    // [b] it asks postgres to execute these queries
+    // [b] it asks postgres (i.e., the corpus: see thecorpus.rs) to execute these queries
     // [c] it stores the results on redis
     // [d] it also updates the redis progress poll data relative to this search
     //
-    let c: &str = cliclone.value_of("c").unwrap();
-    let cap: i32 = c.parse().unwrap();
+    lg_setkey(&thekey);
+    mt_count(MtCounter::Searches);
 
     // recordinitialsizeofworkpile()
-    let mut redisconn = redisconnect(rc.clone());
+    let mut redisconn = RsConn::new(pools);
 
-    let mut thiskey = format!("{}", &thekey);
-    let workpile = rs_scard(&thiskey, &mut redisconn);
+    let mut thiskey = thekey.to_string();
+    let workpile = rs_scard(&thiskey, &mut redisconn)?;
 
     thiskey = format!("{}_poolofwork", &thekey);
-    rs_set_str(&thiskey, &workpile.to_string(), &mut redisconn).unwrap();
+    rs_set_str(&thiskey, &workpile.to_string(), &mut redisconn)?;
+
+    // if one worker cannot go on, the others stop at the end of their current query
+    let abort = Arc::new(AtomicBool::new(false));
 
     // dispatch the workers
     // https://averywagar.com/post/multithreading-rust/
     let handles = (0..workers)
-        .into_iter()
-        .map(|_| {
-            let a = cliclone.clone();
-            thread::spawn( move || {
-                let k = a.value_of("k").unwrap();
-                let pg = a.value_of("p").unwrap();
-                let rc = a.value_of("r").unwrap();
-                grabworker(Uuid::new_v4(), &cap.clone(), &k, ll, &pg, &rc).unwrap();
-            })
+        .map(|i| {
+            let k = thekey.clone();
+            let p = pools.clone();
+            let x = abort.clone();
+            let caught = er_catching();
+            // named so that the log can tell the workers apart; like thread::spawn() this panics if the OS says no
+            thread::Builder::new().name(format!("grab-{}", i)).spawn( move || {
+                lg_setkey(&k);
+                er_setcatching(caught);
+                let r = grabworker(Uuid::new_v4(), &cap, &k, &p, &x);
+                if r.is_err() {
+                    x.store(true, Ordering::SeqCst);
+                }
+                r
+            }).expect("could not start a grab worker")
         })
         .collect::<Vec<thread::JoinHandle<_>>>();
 
-    for thread in handles {
-        thread.join().unwrap();
+    // the first worker to fail speaks for the search; the rest most likely hit the same wall
+    //  a worker that panicked passes its panic on as it was, message and all
+    let failures: Vec<HelperError> = handles.into_iter()
+        .filter_map(|h| h.join().unwrap_or_else(|p| panic::resume_unwind(p)).err())
+        .collect();
+    if let Some(e) = failures.into_iter().next() {
+        return Err(e)
     }
 
     let thiskey = format!("{}_results", &thekey);
-    let hits = rs_scard(&thiskey, &mut redisconn);
-    let m = format!("{} hits were stored", &hits);
-    lfl(m, ll, 1);
+    let hits = rs_scard(&thiskey, &mut redisconn)?;
+    info!("{} hits were stored", &hits);
 
     let resultkey = format!("{}_results", &thekey);
-    resultkey
+    Ok(resultkey)
 }
 
-fn grabworker(id: Uuid, cap: &i32, thekey: &str, ll: i32, pg: &str, rc: &str) -> Result<(), Error> {
+fn grabworker(id: Uuid, cap: &i32, thekey: &str, pools: &DbPools, abort: &AtomicBool) -> HelperResult<()> {
     // this is where all of the work happens
-    let mut redisconn = redisconnect(rc.to_string());
-    let mut psqlclient = postgresconnect(pg.to_string());
+    //  redis hiccups are retried inside RsConn (except for SPOP and SADD); anything that survives that ends this worker (and the search)
+    let mut redisconn = RsConn::new(pools);
+    // whichever way this ends, the temp tables go: see CorpusGuard
+    let mut db = CorpusGuard::open(pools.corpus().as_ref())?;
 
     let mut passes = 0;
     loop {
-        passes = passes + 1;
+        passes += 1;
 
-        // [a] pop a query stored as json in redis
-        let j = rs_spop(&thekey, &mut redisconn);
-        if &j == &"" {
-            let m = format!("{} ran out of work on pass #{}", &id, &passes);
-            lfl(m, ll, 3);
+        if abort.load(Ordering::SeqCst) {
+            debug!("{} stopping on pass #{}: another worker aborted", &id, &passes);
             break
         }
 
+        // [a] pop a query stored as json in redis
+        let j = match rs_spop(thekey, &mut redisconn)? {
+            Some(j) => j,
+            None => {
+                trace!("{} ran out of work on pass #{}", &id, &passes);
+                break
+            }
+        };
+
         // [b] update the polling data
-        let workpile = rs_scard(&thekey, &mut redisconn);
+        let workpile = rs_scard(thekey, &mut redisconn)?;
         let w = workpile.to_string();
 
         let thiskey = format!("{}_remaining", &thekey);
-        rs_set_str(&thiskey, w.as_str(), &mut redisconn).unwrap();
+        rs_set_str(&thiskey, w.as_str(), &mut redisconn)?;
 
         // [c] decode the query
-        let parsed = json::parse(j.as_str()).unwrap();
-        let t = parsed["TempTable"].as_str().unwrap();
-        let q = parsed["PsqlQuery"].as_str().unwrap();
-        let d = parsed["PsqlData"].as_str().unwrap();
-
-        // [d] build a temp table if needed
-        if &t != &"" {
-            psqlclient.execute(t, &[]).ok().expect("TempTable creation failed");
-        }
+        let (t, q, d) = db_workitem(&j)?;
 
-        // [e] execute the main query && [f] iterate through the finds
-        // https://siciarz.net/24-days-of-rust-postgres/
-        // https://docs.rs/postgres/0.19.1/postgres/index.html
-        for row in psqlclient.query(q, &[&d])? {
+        // [d] build a temp table if needed && [e] execute the main query: see db_workitemlines()
+        // [f] iterate through the finds
+        for line in db.workitem(&t, &q, &d)? {
             // [f1] convert the find to JSON
-            // note that we can skip using a DBLine struct here
-            let flds = db_fields();
-            let mut data = JsonValue::new_object();
-            let mut index= 0;
-            for f in flds {
-                if index == 1 {
-                    let r: i32 = row.get(index);
-                    data[f] = r.into();
-                } else {
-                    let r: String = row.get(index);
-                    data[f] = r.into();
-                }
-                index = index + 1;
-            }
+            let data = db_linejson(&line);
 
             // [f2] if you have not hit the cap on finds, store the result in 'querykey_results'
             let thiskey = format!("{}_results", &thekey);
-            let hits = rs_scard(&thiskey, &mut redisconn);
+            let hits = rs_scard(&thiskey, &mut redisconn)?;
 
             if hits >= *cap {
-                rs_del(&thekey, &mut redisconn).unwrap();
+                mt_count(MtCounter::CapTruncations);
+                rs_del(thekey, &mut redisconn)?;
                 break;
             } else {
                 let mut thiskey = format!("{}_results", &thekey);
-                rs_sadd(&thiskey, &data.dump(), &mut redisconn).unwrap();
+                rs_sadd(&thiskey, &cp_dump(&data, pools.format()), &mut redisconn)?;
+                mt_count(MtCounter::HitsStored);
                 thiskey = format!("{}_hitcount", &thekey);
-                rs_set_int(&thiskey, hits + 1, &mut redisconn).unwrap();
+                rs_set_int(&thiskey, hits + 1, &mut redisconn)?;
             }
         }
     }
+
+    // the connection goes back into the pool: do not leave this search's temp tables behind for the next one
+    db.finish()?;
     Ok(())
 }
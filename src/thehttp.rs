@@ -0,0 +1,425 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use json::JsonValue;
+use log::{debug, info, warn};
+use subtle::ConstantTimeEq;
+use tungstenite::http::StatusCode;
+use uuid::Uuid;
+
+use crate::dbfunctions::*;
+use crate::theconfig::*;
+use crate::thedaemon::*;
+use crate::theerrors::*;
+use crate::thepools::*;
+use crate::theschema::*;
+use crate::thestorage::*;
+
+// a client that has not sent its request by now is dropped
+static HPTIMEOUT: Duration = Duration::from_secs(10);
+static MAXHEADSIZE: usize = 16384;
+static MAXBODYSIZE: usize = 4 * 1024 * 1024;
+// finished jobs are forgotten (but their redis keys are not deleted) after this long
+static HPKEEP: Duration = Duration::from_secs(3600);
+
+struct HpJob {
+    kind: String,
+    status: &'static str,
+    finished: Option<Instant>,
+    outcome: Option<Result<String, String>>,
+}
+
+type HpRegistry = Arc<Mutex<HashMap<String, HpJob>>>;
+
+pub struct HpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub authorization: String,
+    pub origin: String,
+    pub contenttype: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct HpServer {
+    // what every connection thread needs
+    pools: DbPools,
+    registry: HpRegistry,
+    queue: Sender<DmJob>,
+    token: String,
+}
+
+pub fn httpapi(cfg: HelperConfig, workers: i32, pools: &DbPools, schema: DbSchema) -> HelperResult<()> {
+    // the HTTP mode lets notebooks and scripts drive the helper without writing to redis by hand
+    //
+    // POST   /search      one work item (as found on the redis work pile), a list of them, or {"items": [...], "options": {"cap": N}}
+    // POST   /vectors     {"items": [...], "options": {"bagging": "flat", ...}}
+    // GET    /jobs/{id}   {"ID": ..., "Type": ..., "Status": "queued"|"running"|"done"|"failed", ...} and the results once done
+    // DELETE /jobs/{id}   forget the job and delete its redis keys
+    //
+    // the jobs themselves are run by --sj threads with the same dm_execute() that the serve mode uses
+    //
+    let listen = format!("{}:{}", cfg.text("hh"), cfg.text("hp"));
+    let slots: i32 = cfg.parse("sj")?;
+    let token = cfg.text("hk");
+    let notoken: i32 = cfg.parse("hkn")?;
+    if token.is_empty() && notoken != 1 {
+        return Err(HelperError::Config("http needs a token (--hk); --hkn 1 runs without one, and lets anyone who can reach the port run SQL".to_string()))
+    }
+    if token.is_empty() {
+        warn!("the HTTP API has no token: anyone who can reach {} can run queries", listen);
+    }
+    let server = TcpListener::bind(&listen)
+        .map_err(|e| HelperError::Config(format!("cannot listen on {}: {}", listen, e)))?;
+
+    let registry: HpRegistry = Arc::new(Mutex::new(HashMap::new()));
+    let (tx, rx) = channel::<DmJob>();
+    let rx = Arc::new(Mutex::new(rx));
+    let schema = Arc::new(schema);
+    for i in 0..slots.max(1) {
+        let c = cfg.clone();
+        let p = pools.clone();
+        let r = registry.clone();
+        let q = rx.clone();
+        let s = schema.clone();
+        // the slots that did start stop when tx is dropped on the way out
+        thread::Builder::new().name(format!("http-{}", i)).spawn( move || {
+            hp_jobslot(&q, &c, workers, &p, &s, &r)
+        }).map_err(|e| HelperError::Config(format!("could not start job slot {} of {}: {}", i + 1, slots.max(1), e)))?;
+    }
+
+    let hs = HpServer { pools: pools.clone(), registry, queue: tx, token };
+    info!("HTTP API listening on {} with {} job slots of {} workers", listen, slots.max(1), workers);
+    for stream in server.incoming().flatten() {
+        let h = hs.clone();
+        thread::spawn(move || hp_serveconnection(stream, &h));
+    }
+    Ok(())
+}
+
+fn hp_jobslot(queue: &Mutex<Receiver<DmJob>>, cfg: &HelperConfig, workers: i32, pools: &DbPools, schema: &DbSchema, registry: &HpRegistry) {
+    // take the next job off the channel, run it, and record how it went
+    loop {
+        let job = match queue.lock().unwrap().recv() {
+            Ok(j) => j,
+            Err(_) => return,
+        };
+        hp_setstatus(registry, &job.key, "running", None);
+        let outcome = dm_execute(&job, cfg, workers, pools, schema);
+        let status = if outcome.is_ok() { "done" } else { "failed" };
+        hp_setstatus(registry, &job.key, status, Some(outcome));
+    }
+}
+
+fn hp_setstatus(registry: &HpRegistry, id: &str, status: &'static str, outcome: Option<Result<String, String>>) {
+    let mut r = registry.lock().unwrap();
+    if let Some(j) = r.get_mut(id) {
+        j.status = status;
+        if outcome.is_some() {
+            j.finished = Some(Instant::now());
+            j.outcome = outcome;
+        }
+    }
+}
+
+fn hp_serveconnection(mut stream: TcpStream, hs: &HpServer) {
+    let _ = stream.set_read_timeout(Some(HPTIMEOUT));
+    let (status, body): (StatusCode, JsonValue) = match hp_readrequest(&mut stream) {
+        Ok(req) => {
+            debug!("HTTP {} {}", req.method, req.path);
+            // only a browser sends Origin: a page on some other site is not to drive this API through it
+            if !req.origin.is_empty() {
+                (StatusCode::FORBIDDEN, hp_message("this API is not for browsers: requests with an Origin header are refused"))
+            } else if !hp_authorized(hs, &req) {
+                (StatusCode::UNAUTHORIZED, hp_message("this API needs 'Authorization: Bearer <token>'"))
+            } else if req.method == "POST" && !hp_isjson(&req.contenttype) {
+                // nor can a form or a text/plain POST (which a browser sends without asking first) get through
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, hp_message("send the body as 'Content-Type: application/json'"))
+            } else {
+                hp_route(hs, &req)
+            }
+        }
+        Err((status, why)) => (status, hp_message(&why)),
+    };
+
+    hp_reply(&mut stream, status, "application/json", &body.dump());
+}
+
+pub fn hp_reply(stream: &mut TcpStream, status: StatusCode, contenttype: &str, body: &str) {
+    // one response and then the connection is closed: nobody keeps a thread busy between requests
+    let reply = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status.as_u16(), status.canonical_reason().unwrap_or(""), contenttype, body.len(), body);
+    let _ = stream.write_all(reply.as_bytes());
+    let _ = stream.flush();
+}
+
+pub fn hp_readrequest(stream: &mut TcpStream) -> Result<HpRequest, (StatusCode, String)> {
+    // read the head, then exactly Content-Length bytes of body
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let headend = loop {
+        if let Some(p) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break p + 4
+        }
+        if buf.len() > MAXHEADSIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "request head is too large".to_string()))
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return Err((StatusCode::BAD_REQUEST, "incomplete request".to_string())),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut parsed = httparse::Request::new(&mut headers);
+    if parsed.parse(&buf[..headend]).is_err() {
+        return Err((StatusCode::BAD_REQUEST, "cannot parse the request".to_string()))
+    }
+    let header = |name: &str| -> String {
+        parsed.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| String::from_utf8_lossy(h.value).trim().to_string())
+            .unwrap_or_default()
+    };
+    let length: usize = match header("Content-Length").as_str() {
+        "" => 0,
+        l => l.parse().map_err(|_| (StatusCode::BAD_REQUEST, "bad Content-Length".to_string()))?,
+    };
+    if length > MAXBODYSIZE {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("the body may not be larger than {} bytes", MAXBODYSIZE)))
+    }
+    let authorization = header("Authorization");
+    let origin = header("Origin");
+    let contenttype = header("Content-Type");
+    let target = parsed.path.unwrap_or("/").to_string();
+    let method = parsed.method.unwrap_or("").to_string();
+
+    let mut body: Vec<u8> = buf[headend..].to_vec();
+    while body.len() < length {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return Err((StatusCode::BAD_REQUEST, "incomplete body".to_string())),
+            Ok(n) => body.extend_from_slice(&chunk[..n]),
+        }
+    }
+    body.truncate(length);
+
+    let (path, query) = match target.split_once('?') {
+        Some((p, q)) => (p.to_string(), q.to_string()),
+        None => (target, String::new()),
+    };
+    Ok(HpRequest { method, path, query, authorization, origin, contenttype, body })
+}
+
+fn hp_authorized(hs: &HpServer, req: &HpRequest) -> bool {
+    // no token: httpapi() only gets this far if --hkn said that anyone who can reach the port may use it
+    if hs.token.is_empty() {
+        return true
+    }
+    match req.authorization.strip_prefix("Bearer ") {
+        Some(t) => bool::from(t.trim().as_bytes().ct_eq(hs.token.as_bytes())),
+        None => false,
+    }
+}
+
+fn hp_isjson(contenttype: &str) -> bool {
+    // "application/json", and "application/json; charset=utf-8"
+    let mediatype = contenttype.split(';').next().unwrap_or("").trim();
+    mediatype.eq_ignore_ascii_case("application/json")
+}
+
+fn hp_route(hs: &HpServer, req: &HpRequest) -> (StatusCode, JsonValue) {
+    let segments: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+    match (req.method.as_str(), segments.as_slice()) {
+        ("POST", ["search"]) => hp_submit(hs, req, "grab"),
+        ("POST", ["vectors"]) => hp_submit(hs, req, "vectors"),
+        ("GET", ["jobs", id]) => hp_job(hs, id),
+        ("DELETE", ["jobs", id]) => hp_forget(hs, id),
+        (_, ["search"]) | (_, ["vectors"]) => (StatusCode::METHOD_NOT_ALLOWED, hp_message("POST only")),
+        (_, ["jobs", _]) => (StatusCode::METHOD_NOT_ALLOWED, hp_message("GET or DELETE only")),
+        _ => (StatusCode::NOT_FOUND, hp_message("try POST /search, POST /vectors or GET /jobs/{id}")),
+    }
+}
+
+fn hp_submit(hs: &HpServer, req: &HpRequest, kind: &str) -> (StatusCode, JsonValue) {
+    // [a] check the work items and the options, [b] put the items where grabber()/vector_prep() look for them
+    // [c] hand the job to a slot and tell the client where to look for it
+    let (items, options) = match hp_parsesubmission(&req.body, &req.query, kind) {
+        Ok(found) => found,
+        Err(e) => return (StatusCode::BAD_REQUEST, hp_message(e.message())),
+    };
+
+    let id = Uuid::new_v4().to_simple().to_string();
+    let job = DmJob { kind: kind.to_string(), key: id.clone(), options };
+    if let Err(e) = dm_validate(&job) {
+        return (StatusCode::BAD_REQUEST, hp_message(e.message()))
+    }
+
+    let mut redisconn = RsConn::new(&hs.pools);
+    for i in &items {
+        if let Err(e) = rs_sadd(&id, i, &mut redisconn) {
+            return (StatusCode::SERVICE_UNAVAILABLE, hp_message(&e.to_string()))
+        }
+    }
+
+    {
+        let mut r = hs.registry.lock().unwrap();
+        // (not Option::is_none_or(), which would need rust 1.82)
+        r.retain(|_, j| match j.finished {
+            Some(f) => f.elapsed() < HPKEEP,
+            None => true,
+        });
+        r.insert(id.clone(), HpJob { kind: kind.to_string(), status: "queued", finished: None, outcome: None });
+    }
+    info!("HTTP queued {} job {} with {} work items", kind, id, items.len());
+    if hs.queue.send(job).is_err() {
+        return (StatusCode::SERVICE_UNAVAILABLE, hp_message("no job slots are running"))
+    }
+
+    let mut data = JsonValue::new_object();
+    data["ID"] = id.as_str().into();
+    data["Status"] = "queued".into();
+    data["Location"] = format!("/jobs/{}", id).into();
+    (StatusCode::ACCEPTED, data)
+}
+
+fn hp_parsesubmission(body: &[u8], query: &str, kind: &str) -> HelperResult<(Vec<String>, JsonValue)> {
+    // {"TempTable": ..., "PsqlQuery": ..., "PsqlData": ...}
+    // [{...}, {...}]
+    // {"items": [{...}, {...}], "options": {...}}
+    // {"table": "lt0448", "start": 1, "end": 250, "options": {...}}: vectors only, and no work items (as --svdb --svs --sve)
+    //  "?cap=N" on the URL is the same as {"options": {"cap": N}}
+    let text = std::str::from_utf8(body).map_err(|_| HelperError::WorkItem("the body is not UTF-8".to_string()))?;
+    let parsed = json::parse(text).map_err(|e| HelperError::WorkItem(format!("the body is not JSON: {}", e)))?;
+    let theoptions = || -> HelperResult<JsonValue> {
+        match &parsed["options"] {
+            JsonValue::Null => Ok(JsonValue::new_object()),
+            o if o.is_object() => Ok(o.clone()),
+            _ => Err(HelperError::WorkItem("\"options\" should be an object".to_string())),
+        }
+    };
+
+    let (items, mut options) = if parsed.is_array() {
+        (parsed.members().cloned().collect::<Vec<JsonValue>>(), JsonValue::new_object())
+    } else if parsed.has_key("table") {
+        if kind != "vectors" {
+            return Err(HelperError::WorkItem("only a vectors job can be given a table instead of work items".to_string()))
+        }
+        if parsed.has_key("items") {
+            return Err(HelperError::WorkItem("send a table or work items, not both".to_string()))
+        }
+        // dm_runjob() finds them with the other options
+        let mut o = theoptions()?;
+        for k in ["table", "start", "end"] {
+            o[k] = parsed[k].clone();
+        }
+        (Vec::new(), o)
+    } else if parsed.has_key("items") {
+        (parsed["items"].members().cloned().collect(), theoptions()?)
+    } else {
+        (vec![parsed], JsonValue::new_object())
+    };
+
+    // the same check that grabworker() will make: better a 400 now than a failed job later
+    let items: Vec<String> = items.iter().map(|i| i.dump()).collect();
+    for i in &items {
+        db_workitem(i)?;
+    }
+
+    for (k, v) in url::form_urlencoded::parse(query.as_bytes()) {
+        options[k.as_ref()] = match v.parse::<i32>() {
+            Ok(n) => n.into(),
+            Err(_) => v.as_ref().into(),
+        };
+    }
+
+    // a table or work items: one or the other (however the table was given)
+    match (items.is_empty(), options["table"].is_null()) {
+        (true, true) => Err(HelperError::WorkItem("there are no work items".to_string())),
+        (false, false) => Err(HelperError::WorkItem("send a table or work items, not both".to_string())),
+        _ => Ok((items, options)),
+    }
+}
+
+fn hp_job(hs: &HpServer, id: &str) -> (StatusCode, JsonValue) {
+    // {"ID": "...", "Type": "grab", "Status": "done", "ResultKey": "..._results", "Hits": 17, "Results": [...]}
+    let (kind, status, outcome) = {
+        let r = hs.registry.lock().unwrap();
+        match r.get(id) {
+            Some(j) => (j.kind.clone(), j.status, j.outcome.clone()),
+            None => return (StatusCode::NOT_FOUND, hp_message(&format!("no job {}", id))),
+        }
+    };
+
+    let mut data = JsonValue::new_object();
+    data["ID"] = id.into();
+    data["Type"] = if kind == "grab" { "search" } else { "vectors" }.into();
+    data["Status"] = status.into();
+
+    let mut redisconn = RsConn::new(&hs.pools);
+    match outcome {
+        Some(Ok(resultkey)) => {
+            data["ResultKey"] = resultkey.as_str().into();
+            match rs_smembers(&resultkey, &mut redisconn) {
+                Ok(found) => {
+                    data["Hits"] = found.len().into();
+                    // the hits and the bags are both stored as JSON
+                    data["Results"] = found.iter()
+                        .map(|f| json::parse(f).unwrap_or_else(|_| f.as_str().into()))
+                        .collect::<Vec<JsonValue>>()
+                        .into();
+                }
+                Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, hp_message(&e.to_string())),
+            }
+        }
+        Some(Err(report)) => {
+            data["Error"] = json::parse(&report).unwrap_or_else(|_| report.into());
+        }
+        None if kind == "grab" => {
+            // the hit count so far
+            if let Ok(n) = rs_scard(&format!("{}_results", id), &mut redisconn) {
+                data["Hits"] = n.into();
+            }
+        }
+        None => (),
+    }
+    (StatusCode::OK, data)
+}
+
+fn hp_forget(hs: &HpServer, id: &str) -> (StatusCode, JsonValue) {
+    // a running job cannot be stopped, so it cannot be deleted either
+    {
+        let mut r = hs.registry.lock().unwrap();
+        match r.get(id) {
+            None => return (StatusCode::NOT_FOUND, hp_message(&format!("no job {}", id))),
+            Some(j) if j.finished.is_none() => return (StatusCode::CONFLICT, hp_message(&format!("job {} is {}", id, j.status))),
+            Some(_) => { r.remove(id); }
+        }
+    }
+    let mut redisconn = RsConn::new(&hs.pools);
+    for suffix in ["", "_results", "_vectorresults", "_poolofwork", "_remaining", "_hitcount"] {
+        if let Err(e) = rs_del(&format!("{}{}", id, suffix), &mut redisconn) {
+            warn!("HTTP could not delete {}{}: {}", id, suffix, e);
+        }
+    }
+    let mut data = JsonValue::new_object();
+    data["ID"] = id.into();
+    data["Status"] = "deleted".into();
+    (StatusCode::OK, data)
+}
+
+fn hp_message(m: &str) -> JsonValue {
+    let mut data = JsonValue::new_object();
+    data["Message"] = m.into();
+    data
+}
@@ -0,0 +1,123 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+use json::JsonValue;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::theerrors::*;
+
+// diagnostics never go to stdout: HipparchiaServer reads stdout for the result key
+//  --l 0 is warnings and errors only; 1 adds info, 2 debug, 3+ trace
+//  other crates (tungstenite, r2d2, postgres, ...) only get to say something at warn or above
+//
+// text:    2021-06-01T12:00:00.123Z INFO  [HRH] [grab-2] [a1b2c3d4] 17 hits were stored
+// json:    {"Time":"2021-06-01T12:00:00.123Z","Level":"INFO","Thread":"grab-2","Key":"a1b2c3d4","Target":"...","Message":"..."}
+
+static SHORTNAME: &str = "HRH";
+
+thread_local! {
+    // the search (or vector run, or websocket poll) that this thread is working for
+    static SEARCHKEY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+struct HelperLogger {
+    level: LevelFilter,
+    json: bool,
+    ours: &'static str,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for HelperLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.target().starts_with(self.ours) {
+            metadata.level() <= self.level
+        } else {
+            metadata.level() <= Level::Warn
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+        let when = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+        let current = thread::current();
+        let who: String = match current.name() {
+            Some(n) => n.to_string(),
+            None => format!("{:?}", current.id()),
+        };
+        let key: Option<String> = SEARCHKEY.with(|k| k.borrow().clone());
+
+        let line = if self.json {
+            let mut data = JsonValue::new_object();
+            data["Time"] = when.into();
+            data["Level"] = record.level().as_str().into();
+            data["Thread"] = who.into();
+            if let Some(k) = key {
+                data["Key"] = k.into();
+            }
+            data["Target"] = record.target().into();
+            data["Message"] = record.args().to_string().into();
+            data.dump()
+        } else {
+            let k = key.map(|k| format!(" [{}]", k)).unwrap_or_default();
+            format!("{} {:<5} [{}] [{}]{} {}", when, record.level(), SHORTNAME, who, k, record.args())
+        };
+
+        // a log line that cannot be written is not worth dying over
+        if let Ok(mut o) = self.out.lock() {
+            let _ = writeln!(o, "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut o) = self.out.lock() {
+            let _ = o.flush();
+        }
+    }
+}
+
+pub fn lg_init(ll: i32, format: &str, logfile: Option<&str>) -> HelperResult<()> {
+    // set up the logger once, as soon as the settings are known
+    let json = match format {
+        "text" => false,
+        "json" => true,
+        _ => return Err(HelperError::Config(format!("unknown log format '{}': use text or json", format))),
+    };
+    let out: Box<dyn Write + Send> = match logfile.filter(|f| !f.is_empty()) {
+        Some(f) => Box::new(OpenOptions::new().create(true).append(true).open(f)
+            .map_err(|e| HelperError::Config(format!("cannot open the log file {}: {}", f, e)))?),
+        None => Box::new(io::stderr()),
+    };
+    let level = match ll {
+        l if l <= 0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let ours = module_path!().split("::").next().unwrap_or("");
+    let logger = HelperLogger { level, json, ours, out: Mutex::new(out) };
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|e| HelperError::Config(format!("cannot set up logging: {}", e)))?;
+    log::set_max_level(level.max(LevelFilter::Warn));
+    Ok(())
+}
+
+pub fn lg_setkey(key: &str) {
+    // every line this thread logs from now on carries the key
+    SEARCHKEY.with(|k| *k.borrow_mut() = Some(key.to_string()));
+}
+
+pub fn lg_clearkey() {
+    // back to logging for nobody in particular (e.g., a serve slot between jobs)
+    SEARCHKEY.with(|k| *k.borrow_mut() = None);
+}
@@ -0,0 +1,182 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use tungstenite::http::StatusCode;
+
+use crate::theerrors::*;
+use crate::thehttp::*;
+
+// counters and histograms in the Prometheus text format (version 0.0.4)
+//  --mp N serves them at http://--mh:N/metrics: for the modes that stay up (websocket, serve, http)
+//  --mf path writes them to a file as the process exits: for the one-shot grab and vectors runs
+//      (point node_exporter's textfile collector at the directory)
+
+#[derive(Clone, Copy)]
+pub enum MtCounter {
+    Searches,
+    VectorRuns,
+    Queries,
+    RowsScanned,
+    HitsStored,
+    CapTruncations,
+    WsConnections,
+    RedisErrors,
+    RedisRetries,
+    PostgresErrors,
+}
+
+// name and help text, in the same order as MtCounter
+static COUNTERS: [(&str, &str); 10] = [
+    ("hrh_searches_total", "Searches run by grabber()."),
+    ("hrh_vector_runs_total", "Bag of words runs started by vector_prep()."),
+    ("hrh_queries_total", "Work item queries executed against postgres."),
+    ("hrh_rows_scanned_total", "Rows that postgres returned for those queries."),
+    ("hrh_hits_stored_total", "Hits stored in <key>_results."),
+    ("hrh_cap_truncations_total", "Queries that were cut short because the search had reached its hit cap."),
+    ("hrh_websocket_connections_total", "Websocket clients that completed the handshake."),
+    ("hrh_redis_errors_total", "Redis commands that failed (after retrying, for those that are safe to retry)."),
+    ("hrh_redis_retries_total", "Redis commands that were retried after a transient failure."),
+    ("hrh_postgres_errors_total", "Failed postgres queries and connection attempts."),
+];
+
+// the vector_prep() stages ([A: ...] through [J: ...] in the debug log)
+static STAGES: [&str; 10] = ["A", "B", "C", "D", "E", "F", "G", "H", "I", "J"];
+static BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+// a scraper that does not send its request by now is dropped
+static MTTIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct MtHistogram {
+    // counts[i] is the number of observations <= BUCKETS[i]; the last slot is +Inf
+    counts: [AtomicU64; 13],
+    micros: AtomicU64,
+}
+
+lazy_static! {
+    static ref TALLIES: Vec<AtomicU64> = COUNTERS.iter().map(|_| AtomicU64::new(0)).collect();
+    static ref STAGETIMES: Vec<MtHistogram> = STAGES.iter().map(|_| MtHistogram::default()).collect();
+}
+
+static WSOPEN: AtomicI64 = AtomicI64::new(0);
+
+pub fn mt_count(c: MtCounter) {
+    mt_add(c, 1);
+}
+
+pub fn mt_add(c: MtCounter, n: u64) {
+    TALLIES[c as usize].fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn mt_wsopen(delta: i64) {
+    // +1 when a websocket client arrives, -1 when it leaves
+    WSOPEN.fetch_add(delta, Ordering::Relaxed);
+}
+
+pub fn mt_lap(stage: &str, elapsed: Duration, previous: &mut Duration) {
+    // vector_prep() keeps a running total; the histogram wants the time spent in this stage alone
+    let spent = elapsed.saturating_sub(*previous);
+    *previous = elapsed;
+    let h = match STAGES.iter().position(|s| *s == stage) {
+        Some(i) => &STAGETIMES[i],
+        None => return,
+    };
+    let seconds = spent.as_secs_f64();
+    for (i, b) in BUCKETS.iter().enumerate() {
+        if seconds <= *b {
+            h.counts[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    h.counts[BUCKETS.len()].fetch_add(1, Ordering::Relaxed);
+    h.micros.fetch_add(spent.as_micros() as u64, Ordering::Relaxed);
+}
+
+pub fn mt_render() -> String {
+    // everything we know, in the text format that Prometheus scrapes
+    let mut out = String::new();
+    for (i, (name, help)) in COUNTERS.iter().enumerate() {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, TALLIES[i].load(Ordering::Relaxed));
+    }
+
+    let name = "hrh_websocket_connections_open";
+    let _ = writeln!(out, "# HELP {} Websocket clients connected right now.\n# TYPE {} gauge\n{} {}",
+                     name, name, name, WSOPEN.load(Ordering::Relaxed));
+
+    let name = "hrh_vector_stage_seconds";
+    let _ = writeln!(out, "# HELP {} Time spent in each vector_prep() stage.\n# TYPE {} histogram", name, name);
+    for (stage, h) in STAGES.iter().zip(STAGETIMES.iter()) {
+        for (i, b) in BUCKETS.iter().enumerate() {
+            let _ = writeln!(out, "{}_bucket{{stage=\"{}\",le=\"{}\"}} {}", name, stage, b, h.counts[i].load(Ordering::Relaxed));
+        }
+        let count = h.counts[BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{stage=\"{}\",le=\"+Inf\"}} {}", name, stage, count);
+        let _ = writeln!(out, "{}_sum{{stage=\"{}\"}} {}", name, stage, h.micros.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(out, "{}_count{{stage=\"{}\"}} {}", name, stage, count);
+    }
+    out
+}
+
+pub fn mt_serve(host: &str, port: &str) -> HelperResult<()> {
+    // answer GET /metrics on a port of its own, in the background, for as long as the process lives
+    let listen = format!("{}:{}", host, port);
+    let server = TcpListener::bind(&listen)
+        .map_err(|e| HelperError::Config(format!("cannot serve metrics on {}: {}", listen, e)))?;
+    info!("metrics are at http://{}/metrics", listen);
+    thread::Builder::new().name("metrics".to_string()).spawn( move || {
+        // scrapes are small and rare: one at a time is plenty
+        for stream in server.incoming().flatten() {
+            mt_serveconnection(stream);
+        }
+    }).expect("could not start the metrics listener");
+    Ok(())
+}
+
+fn mt_serveconnection(mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(MTTIMEOUT));
+    let (status, body) = match hp_readrequest(&mut stream) {
+        Ok(req) if req.method == "GET" && req.path == "/metrics" => (StatusCode::OK, mt_render()),
+        Ok(req) => {
+            debug!("metrics listener ignored {} {}", req.method, req.path);
+            (StatusCode::NOT_FOUND, "try GET /metrics\n".to_string())
+        }
+        Err((status, why)) => (status, format!("{}\n", why)),
+    };
+    hp_reply(&mut stream, status, "text/plain; version=0.0.4", &body);
+}
+
+pub struct MtDump {
+    // when this goes out of scope (at the end of the run, however it ended) the metrics are written to the file
+    file: Option<String>,
+}
+
+impl MtDump {
+    pub fn new(file: Option<String>) -> MtDump {
+        MtDump { file: file.filter(|f| !f.is_empty()) }
+    }
+}
+
+impl Drop for MtDump {
+    fn drop(&mut self) {
+        let f = match &self.file {
+            Some(f) => f,
+            None => return,
+        };
+        // write and then rename so that a collector never reads half a file
+        let tmp = format!("{}.tmp", f);
+        match fs::write(&tmp, mt_render()).and_then(|_| fs::rename(&tmp, f)) {
+            Ok(_) => debug!("metrics written to {}", f),
+            Err(e) => warn!("could not write the metrics to {}: {}", f, e),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use postgres_native_tls::MakeTlsConnector;
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+
+use crate::dbfunctions::*;
+use crate::thecompat::*;
+use crate::thecorpus::*;
+use crate::theerrors::*;
+use crate::themetrics::*;
+use crate::theredis::*;
+use crate::thestorage::*;
+
+// how long a checkout waits for a connection before giving up
+pub static POOLTIMEOUT: Duration = Duration::from_secs(5);
+
+pub type PgManager = PostgresConnectionManager<MakeTlsConnector>;
+pub type PgConnection = PooledConnection<PgManager>;
+
+#[derive(Clone)]
+pub struct DbPools {
+    // one set of postgres connections, one store and one corpus for every thread in the process
+    //  cloning is cheap: the clones all share the same pool, the same store and the same corpus
+    pg: Pool<PgManager>,
+    store: Arc<dyn HelperStore>,
+    corpus: Arc<dyn CorpusSource>,
+    pgname: String,
+    // how the hits, bags and progress messages are written out (see thecompat.rs)
+    format: HelperFormat,
+}
+
+impl DbPools {
+    pub fn new(pg: &str, rc: &str, size: u32) -> HelperResult<DbPools> {
+        // "memory://" instead of a redis login keeps everything inside this process
+        let store: Arc<dyn HelperStore> = if rc.trim() == MEMORYSTORE {
+            Arc::new(MemoryStore::new())
+        } else {
+            Arc::new(RedisStore::new(rc, size)?)
+        };
+        DbPools::withstore(pg, store, size)
+    }
+
+    pub fn withstore(pg: &str, store: Arc<dyn HelperStore>, size: u32) -> HelperResult<DbPools> {
+        // nothing connects yet: a connection is opened on first checkout and then kept warm
+        //  every checkout is health-checked first (SELECT '') and a dead connection is replaced
+        let (config, tls) = postgresconfig(pg).map_err(HelperError::Config)?;
+        let pgname = pg_describe(&config);
+        let pgpool = Pool::builder()
+            .max_size(size)
+            .min_idle(Some(0))
+            .test_on_check_out(true)
+            .connection_timeout(POOLTIMEOUT)
+            .build(PostgresConnectionManager::new(config, tls))
+            .map_err(|e| HelperError::Postgres(format!("could not set up the postgres pool: {}", e)))?;
+
+        // the bagging code reads the same database unless withcorpus() says otherwise
+        let corpus = Arc::new(PgCorpus::new(pgpool.clone(), &pgname));
+        Ok(DbPools { pg: pgpool, store, corpus, pgname, format: HelperFormat::Rust })
+    }
+
+    pub fn withcorpus(mut self, corpus: Arc<dyn CorpusSource>) -> DbPools {
+        // e.g., a FileCorpus for --svc
+        self.corpus = corpus;
+        self
+    }
+
+    pub fn withformat(mut self, format: HelperFormat) -> DbPools {
+        // --compat go
+        self.format = format;
+        self
+    }
+
+    pub fn pg(&self) -> HelperResult<PgConnection> {
+        pg_checkout(&self.pg, &self.pgname)
+    }
+
+    pub fn store(&self) -> &Arc<dyn HelperStore> {
+        &self.store
+    }
+
+    pub fn corpus(&self) -> &Arc<dyn CorpusSource> {
+        &self.corpus
+    }
+
+    pub fn format(&self) -> HelperFormat {
+        self.format
+    }
+}
+
+pub fn pg_checkout(pool: &Pool<PgManager>, name: &str) -> HelperResult<PgConnection> {
+    pool.get().map_err(|e| {
+        mt_count(MtCounter::PostgresErrors);
+        HelperError::Postgres(format!("could not connect to {}: {}", name, e))
+    })
+}
+
+// --r memory:// (and the python module's redis="memory://") uses a MemoryStore instead of redis
+pub static MEMORYSTORE: &str = "memory://";
+
+// what to log in to if nobody says otherwise
+pub static PGDEFAULT: &str = r#"{"Host": "localhost", "Port": 5432, "User": "hippa_wr", "Pass": "", "DBName": "hipparchiaDB"}"#;
+pub static RSDEFAULT: &str = r#"{"Addr": "localhost:6379", "Password": "", "DB": 0}"#;
+
+pub fn poolsize(workers: i32) -> u32 {
+    // every worker holds one of each and the thread that dispatched them holds one more
+    (workers.max(1) + 1) as u32
+}
+
+pub fn servepoolsize(workers: i32, slots: i32) -> u32 {
+    // serve: every job slot holds one more redis connection for BLPOP on top of what its job needs
+    (poolsize(workers) + 1) * slots.max(1) as u32
+}
@@ -0,0 +1,157 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use json::JsonValue;
+use lazy_static::lazy_static;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use crate::theerrors::*;
+use crate::thefiles::*;
+use crate::thegrabber::*;
+use crate::thelogging::*;
+use crate::thepools::*;
+use crate::thevectors::*;
+use crate::thewebsockets::*;
+
+// the python module: HipparchiaServer can import this instead of spawning the CLI and reading its stdout
+//
+//  import hipparchia_rust_dbhelper as hrh
+//  resultkey = hrh.grab("a1b2c3d4", cap=200, workers=5, postgres=pgjson, redis=rsjson)
+//  bags = hrh.build_bags("e5f6a7b8", bagging="flat")        # {"line/lt0448w001/1": ["gallia", ...], ...}
+//  bags = hrh.build_bags("mine", table="lt0448", corpus="/data/hipparchia", redis="memory://")     # no servers at all
+//  progress = hrh.poll("a1b2c3d4")                          # the websocket's schema 2 snapshot as a dict
+//
+// a failure raises HelperException(kind, exitcode, message): the same three things as the CLI's stderr report
+// the GIL is released while the work is done, so the server's other threads keep going
+
+create_exception!(hipparchia_rust_dbhelper, HelperException, PyException);
+
+lazy_static! {
+    // one set of pools per login (size, and corpus) for the life of the interpreter, so that every call is not a reconnect
+    static ref POOLS: Mutex<HashMap<(String, String, u32, String), DbPools>> = Mutex::new(HashMap::new());
+}
+
+fn py_pools(postgres: Option<String>, redis: Option<String>, workers: i32, corpus: Option<String>) -> PyResult<DbPools> {
+    let pg = postgres.unwrap_or_else(|| PGDEFAULT.to_string());
+    let rc = redis.unwrap_or_else(|| RSDEFAULT.to_string());
+    let size = poolsize(workers);
+    let cd = corpus.unwrap_or_default();
+    let mut known = POOLS.lock().unwrap();
+    if let Some(p) = known.get(&(pg.clone(), rc.clone(), size, cd.clone())) {
+        return Ok(p.clone())
+    }
+    let mut p = DbPools::new(&pg, &rc, size).map_err(py_error)?;
+    if !cd.is_empty() {
+        p = p.withcorpus(Arc::new(FileCorpus::new(&cd).map_err(py_error)?));
+    }
+    known.insert((pg, rc, size, cd), p.clone());
+    Ok(p)
+}
+
+fn py_error(e: HelperError) -> PyErr {
+    HelperException::new_err((e.kind(), e.exitcode(), e.message().to_string()))
+}
+
+fn py_json(py: Python, j: &JsonValue) -> PyObject {
+    match j {
+        JsonValue::Null => py.None(),
+        JsonValue::Boolean(b) => b.to_object(py),
+        JsonValue::Number(_) => match j.as_i64() {
+            Some(i) => i.to_object(py),
+            None => j.as_f64().unwrap_or(f64::NAN).to_object(py),
+        },
+        JsonValue::Array(a) => PyList::new(py, a.iter().map(|v| py_json(py, v))).to_object(py),
+        JsonValue::Object(_) => {
+            let d = PyDict::new(py);
+            for (k, v) in j.entries() {
+                let _ = d.set_item(k, py_json(py, v));
+            }
+            d.to_object(py)
+        }
+        _ => j.as_str().unwrap_or_default().to_object(py),
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (key, cap = 200, workers = 5, postgres = None, redis = None))]
+fn grab(py: Python, key: String, cap: i32, workers: i32, postgres: Option<String>, redis: Option<String>) -> PyResult<String> {
+    // run the work items under key, exactly as "grab --k key" does; returns "<key>_results"
+    let pools = py_pools(postgres, redis, workers, None)?;
+    py.allow_threads(move || {
+        // grabber() tags this (python's) thread with the key: it should not outlive the call
+        let r = grabber(cap, key, workers, &pools);
+        lg_clearkey();
+        r
+    }).map_err(py_error)
+}
+
+#[pyfunction]
+#[pyo3(signature = (key, bagging = "winnertakesall".to_string(), bagsize = 1, stopheadwords = None, stopinflected = None,
+                    store = false, table = None, start = 1, end = None, corpus = None, postgres = None, redis = None))]
+#[allow(clippy::too_many_arguments)]
+fn build_bags(py: Python, key: String, bagging: String, bagsize: i32, stopheadwords: Option<Vec<String>>,
+              stopinflected: Option<Vec<String>>, store: bool, table: Option<String>, start: i32, end: Option<i32>,
+              corpus: Option<String>, postgres: Option<String>, redis: Option<String>) -> PyResult<PyObject> {
+    // the bags for the work items under key, as {locus: [word, word, ...]}
+    //  table="lt0448" (start=, end=) reads those lines instead, as --svdb (--svs, --sve) do
+    //  corpus="/some/dir" reads the tables from CSV/JSONL files, as --svc does
+    //  store=True also leaves them in "<key>_vectorresults" the way the CLI does
+    let pools = py_pools(postgres, redis, 1, corpus)?;
+    let stops = (stopheadwords.map(|w| w.join(" ")), stopinflected.map(|w| w.join(" ")));
+    let st = py.allow_threads(move || {
+        // the key tags this (python's) thread for the log: it is cleared however the call ends
+        lg_setkey(&key);
+        let bagit = || -> HelperResult<BagState> {
+            let db = table.unwrap_or_default();
+            let mut p = BagPipeline::standard(&bagging, bagsize, &db, start, end.unwrap_or(i32::MAX), stops)?;
+            if !store {
+                p.remove("J")?;
+            }
+            p.execute(&key, &pools)
+        };
+        let st = bagit();
+        lg_clearkey();
+        st
+    }).map_err(py_error)?;
+
+    let bags = PyDict::new(py);
+    for (locus, bag) in &st.bags {
+        bags.set_item(locus, bag.split_whitespace().collect::<Vec<&str>>())?;
+    }
+    Ok(bags.to_object(py))
+}
+
+#[pyfunction]
+#[pyo3(signature = (key, postgres = None, redis = None))]
+fn poll(py: Python, key: String, postgres: Option<String>, redis: Option<String>) -> PyResult<PyObject> {
+    // {"Schema": 2, "Type": "snapshot", "ID": key, "Active": True, "Hitcount": 12, "Percent": 37.5, ...}
+    let pools = py_pools(postgres, redis, 1, None)?;
+    let snapshot = py.allow_threads(move || ws_pollsnapshot(&key, &pools)).map_err(py_error)?;
+    Ok(py_json(py, &snapshot))
+}
+
+#[pyfunction]
+#[pyo3(signature = (level = 0, format = "text".to_string(), logfile = None))]
+fn init_logging(level: i32, format: String, logfile: Option<String>) -> PyResult<()> {
+    // the same as --l, --logformat and --logfile; without this the module says nothing at all
+    lg_init(level, &format, logfile.as_deref()).map_err(py_error)
+}
+
+#[pymodule]
+fn hipparchia_rust_dbhelper(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("HelperException", py.get_type::<HelperException>())?;
+    m.add_function(wrap_pyfunction!(grab, m)?)?;
+    m.add_function(wrap_pyfunction!(build_bags, m)?)?;
+    m.add_function(wrap_pyfunction!(poll, m)?)?;
+    m.add_function(wrap_pyfunction!(init_logging, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::thread;
+use std::time::Duration;
+
+use r2d2::{Pool, PooledConnection};
+use redis::{Commands, ErrorKind, RedisError, RedisResult};
+
+use crate::dbfunctions::*;
+use crate::theerrors::*;
+use crate::themetrics::*;
+use crate::thepools::*;
+use crate::thestorage::*;
+
+// a transient failure gets this many more tries, waiting RSBACKOFF, 2 x RSBACKOFF, 4 x RSBACKOFF, ...
+static RSRETRIES: u32 = 3;
+static RSBACKOFF: Duration = Duration::from_millis(250);
+
+pub type RedisConnection = PooledConnection<redis::Client>;
+
+pub struct RedisStore {
+    // the store that HipparchiaServer shares with us
+    pool: Pool<redis::Client>,
+    name: String,
+}
+
+impl RedisStore {
+    pub fn new(rc: &str, size: u32) -> HelperResult<RedisStore> {
+        // nothing connects yet: a connection is opened on first checkout and then kept warm
+        //  every checkout is health-checked first (PING) and a dead connection is replaced
+        let info = redisconninfo(rc).map_err(HelperError::Config)?;
+        let name = rs_describe(&info);
+        let client = redis::Client::open(info)
+            .map_err(|e| HelperError::Config(format!("could not set up the redis pool: {}", e)))?;
+        let pool = Pool::builder()
+            .max_size(size)
+            .min_idle(Some(0))
+            .test_on_check_out(true)
+            .connection_timeout(POOLTIMEOUT)
+            .build(client)
+            .map_err(|e| HelperError::Redis(format!("could not set up the redis pool: {}", e)))?;
+        Ok(RedisStore { pool, name })
+    }
+}
+
+impl HelperStore for RedisStore {
+    fn connect(&self) -> Box<dyn StoreConn> {
+        Box::new(RedisConn { pool: self.pool.clone(), name: self.name.clone(), conn: None })
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+}
+
+struct RedisConn {
+    // a redis connection that heals itself: on a transient error the (broken) connection is handed back
+    // to the pool, which discards it, and the command is retried on a fresh one
+    //  but only a command that can safely run twice: if the connection drops after redis ran SPOP, BLPOP, RPUSH or
+    //  SADD but before we heard back, a retry would pop the next item, or queue the job again; those fail at once
+    pool: Pool<redis::Client>,
+    name: String,
+    conn: Option<RedisConnection>,
+}
+
+impl RedisConn {
+    fn run<T, F>(&mut self, retry: bool, mut f: F) -> RsResult<T>
+    where
+        F: FnMut(&mut redis::Connection) -> RedisResult<T>,
+    {
+        let mut attempt: u32 = 0;
+        loop {
+            let outcome: RsResult<T> = match self.checkout() {
+                Ok(c) => f(c).map_err(rs_classify),
+                // the pool has already spent POOLTIMEOUT trying to connect: do not wait all over again
+                Err(e) => {
+                    mt_count(MtCounter::RedisErrors);
+                    return Err(RsError::Transient(e.message().to_string()))
+                }
+            };
+            match outcome {
+                Err(RsError::Transient(e)) if !retry => {
+                    mt_count(MtCounter::RedisErrors);
+                    self.conn = None;
+                    return Err(RsError::Transient(format!("{} (not retried: the command may already have run)", e)))
+                }
+                Err(RsError::Transient(_)) if attempt < RSRETRIES => {
+                    mt_count(MtCounter::RedisRetries);
+                    self.conn = None;
+                    thread::sleep(RSBACKOFF * 2u32.pow(attempt));
+                    attempt += 1;
+                }
+                Err(RsError::Transient(e)) => {
+                    mt_count(MtCounter::RedisErrors);
+                    self.conn = None;
+                    return Err(RsError::Transient(format!("{} (gave up after {} retries)", e, RSRETRIES)))
+                }
+                Err(e) => {
+                    mt_count(MtCounter::RedisErrors);
+                    return Err(e)
+                }
+                ok => return ok,
+            }
+        }
+    }
+
+    fn checkout(&mut self) -> HelperResult<&mut redis::Connection> {
+        if self.conn.is_none() {
+            let c = self.pool.get().map_err(|e| HelperError::Redis(format!("could not connect to {}: {}", self.name, e)))?;
+            self.conn = Some(c);
+        }
+        Ok(self.conn.as_mut().unwrap())
+    }
+}
+
+impl StoreConn for RedisConn {
+    fn scard(&mut self, k: &str) -> RsResult<i32> {
+        self.run(true, |r| r.scard(k))
+    }
+
+    fn spop(&mut self, k: &str) -> RsResult<Option<String>> {
+        self.run(false, |r| r.spop(k))
+    }
+
+    fn sadd(&mut self, k: &str, v: &[String]) -> RsResult<()> {
+        self.run(false, |r| r.sadd(k, v))
+    }
+
+    fn smembers(&mut self, k: &str) -> RsResult<Vec<String>> {
+        self.run(true, |r| r.smembers(k))
+    }
+
+    fn get(&mut self, k: &str) -> RsResult<Option<String>> {
+        self.run(true, |r| r.get(k))
+    }
+
+    fn set(&mut self, k: &str, v: &str) -> RsResult<()> {
+        self.run(true, |r| r.set(k, v))
+    }
+
+    fn del(&mut self, k: &str) -> RsResult<i32> {
+        self.run(true, |r| r.del(k))
+    }
+
+    fn exists(&mut self, k: &str) -> RsResult<bool> {
+        self.run(true, |r| r.exists(k))
+    }
+
+    fn rpush(&mut self, k: &str, v: &str) -> RsResult<()> {
+        self.run(false, |r| r.rpush(k, v))
+    }
+
+    fn blpop(&mut self, k: &str, timeout: usize) -> RsResult<Option<String>> {
+        self.run(false, |r| r.blpop::<_, Option<(String, String)>>(k, timeout).map(|o| o.map(|(_, v)| v)))
+    }
+
+    fn ping(&mut self) -> RsResult<()> {
+        self.run(true, |r| redis::cmd("PING").query::<String>(r).map(|_| ()))
+    }
+}
+
+fn rs_classify(e: RedisError) -> RsError {
+    let transient = e.is_io_error()
+        || e.is_timeout()
+        || e.is_connection_dropped()
+        || e.is_connection_refusal()
+        || matches!(e.kind(), ErrorKind::BusyLoadingError | ErrorKind::TryAgain | ErrorKind::MasterDown | ErrorKind::ClusterDown);
+    if transient {
+        RsError::Transient(e.to_string())
+    } else {
+        RsError::Persistent(e.to_string())
+    }
+}
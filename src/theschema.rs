@@ -0,0 +1,148 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::{HashMap, HashSet};
+
+use postgres::Row;
+
+use crate::theerrors::*;
+use crate::thestructs::*;
+
+// every author table ("lt0448", "gr0012", ...) has to have these...
+static LINECOLUMNS: [&str; 11] = ["index", "wkuniversalid", "level_05_value", "level_04_value", "level_03_value",
+    "level_02_value", "level_01_value", "level_00_value", "marked_up_line", "accented_line", "stripped_line"];
+// ...and these are read as "" if a table does not have them
+static OPTIONALLINECOLUMNS: [&str; 2] = ["hyphenated_words", "annotations"];
+
+// the bagging code needs all of these: related_headwords arrived with HipparchiaBuilder 1.5.0b
+static MORPHCOLUMNS: [&str; 4] = ["observed_form", "xrefs", "prefixrefs", "related_headwords"];
+static COUNTCOLUMNS: [&str; 2] = ["entry_name", "total_count"];
+
+static CURRENTBUILDER: &str = "1.5.0b+";
+static OLDBUILDER: &str = "pre-1.5.0b";
+
+pub struct DbSchema {
+    // what the database we are pointed at actually looks like
+    pub generation: String,
+    pub linetable: Option<String>,
+    columns: HashMap<String, HashSet<String>>,
+}
+
+impl DbSchema {
+    pub fn requirelines(&self) -> HelperResult<()> {
+        // only checked if there is an author table to look at: an empty database will fail soon enough
+        match &self.linetable {
+            Some(t) => self.require(t, &LINECOLUMNS, "an author table"),
+            None => Ok(()),
+        }
+    }
+
+    pub fn requiremorphology(&self) -> HelperResult<()> {
+        self.require("latin_morphology", &MORPHCOLUMNS, "the vectorizer")?;
+        self.require("greek_morphology", &MORPHCOLUMNS, "the vectorizer")?;
+        self.require("dictionary_headword_wordcounts", &COUNTCOLUMNS, "the 'winnertakesall' bagger")
+    }
+
+    fn require(&self, table: &str, wanted: &[&str], who: &str) -> HelperResult<()> {
+        let found = match self.columns.get(table) {
+            Some(c) => c,
+            None => return Err(HelperError::Schema(format!("{} needs the table '{}', which this database does not have", who, table))),
+        };
+        let missing: Vec<&str> = wanted.iter().filter(|c| !found.contains(**c)).copied().collect();
+        if missing.is_empty() {
+            return Ok(())
+        }
+        let mut m = format!("{} needs {}.{{{}}}, which this database does not have", who, table, missing.join(", "));
+        if self.generation == OLDBUILDER {
+            m = format!("{}: it was built by HipparchiaBuilder {}; rebuild it with HipparchiaBuilder {}", m, OLDBUILDER, CURRENTBUILDER);
+        }
+        Err(HelperError::Schema(m))
+    }
+}
+
+pub fn sc_detect(pg: &mut postgres::Client, linetable: Option<&str>) -> HelperResult<DbSchema> {
+    // look the database over once at startup instead of finding out halfway through a search
+    //  "linetable" is the author table we are about to read; otherwise any one of them will do
+    let linetable: Option<String> = match linetable {
+        Some(t) => Some(t.to_string()),
+        None => pg.query("SELECT table_name::text FROM information_schema.tables WHERE table_schema = current_schema() \
+                AND table_name ~ '^[a-z]{2}[0-9]{4}$' ORDER BY table_name LIMIT 1", &[])?
+            .first()
+            .map(|r| r.get(0)),
+    };
+
+    let tables = sc_tables(&linetable);
+    let mut columns: HashMap<String, HashSet<String>> = HashMap::new();
+    let q = "SELECT table_name::text, column_name::text FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = ANY($1)";
+    for row in pg.query(q, &[&tables])? {
+        columns.entry(row.get(0)).or_default().insert(row.get(1));
+    }
+
+    Ok(sc_fromcolumns(linetable, columns))
+}
+
+pub fn sc_tables(linetable: &Option<String>) -> Vec<String> {
+    // the tables that sc_detect() looks at
+    let mut tables: Vec<String> = vec!["latin_morphology".to_string(), "greek_morphology".to_string(), "dictionary_headword_wordcounts".to_string()];
+    if let Some(t) = linetable {
+        tables.push(t.clone());
+    }
+    tables
+}
+
+pub fn sc_fromcolumns(linetable: Option<String>, columns: HashMap<String, HashSet<String>>) -> DbSchema {
+    // table --> its columns, however they were found (information_schema, or the headers of the --svc files)
+    let modern = columns.get("latin_morphology").is_some_and(|c| c.contains("related_headwords"));
+    let generation = if modern { CURRENTBUILDER } else { OLDBUILDER };
+    DbSchema { generation: generation.to_string(), linetable, columns }
+}
+
+pub fn sc_dbline(row: &Row) -> DBLine {
+    // the column mapping for author tables: read by name, and let the optional columns be absent
+    //  a column that is there is read like any other: a NULL or a wrong type should not turn into ""
+    let optional = |c: &str| -> String {
+        match row.columns().iter().any(|col| col.name() == c) {
+            true => row.get(c),
+            false => String::new(),
+        }
+    };
+    DBLine {
+        idx: row.get("index"),
+        uid: row.get("wkuniversalid"),
+        l5: row.get("level_05_value"),
+        l4: row.get("level_04_value"),
+        l3: row.get("level_03_value"),
+        l2: row.get("level_02_value"),
+        l1: row.get("level_01_value"),
+        l0: row.get("level_00_value"),
+        mu: row.get("marked_up_line"),
+        ac: row.get("accented_line"),
+        st: row.get("stripped_line"),
+        hy: optional(OPTIONALLINECOLUMNS[0]),
+        an: optional(OPTIONALLINECOLUMNS[1]),
+    }
+}
+
+pub fn sc_fileline(row: &HashMap<String, String>) -> Result<DBLine, String> {
+    // the same mapping for a line read from a file: every value arrives as text
+    let get = |c: &str| -> Result<String, String> { row.get(c).cloned().ok_or_else(|| format!("no '{}'", c)) };
+    let optional = |c: &str| -> String { row.get(c).cloned().unwrap_or_default() };
+    let idx = get("index")?;
+    Ok(DBLine {
+        idx: idx.trim().parse().map_err(|_| format!("'{}' is not an index", idx))?,
+        uid: get("wkuniversalid")?,
+        l5: get("level_05_value")?,
+        l4: get("level_04_value")?,
+        l3: get("level_03_value")?,
+        l2: get("level_02_value")?,
+        l1: get("level_01_value")?,
+        l0: get("level_00_value")?,
+        mu: get("marked_up_line")?,
+        ac: get("accented_line")?,
+        st: get("stripped_line")?,
+        hy: optional(OPTIONALLINECOLUMNS[0]),
+        an: optional(OPTIONALLINECOLUMNS[1]),
+    })
+}
@@ -0,0 +1,294 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::thepools::*;
+
+// where the work items, the hits, the bags, the progress poll and the job queue live
+//  redis (theredis.rs) is what HipparchiaServer shares with us; MemoryStore lets everything run in one
+//  process with no server at all (--r memory://, the python module, tests)
+//
+// the rs_* functions below are what the rest of the crate calls: they do not care which store is behind them
+
+#[derive(Debug)]
+pub enum RsError {
+    // worth another try: the connection dropped, timed out, was refused, or the server is still loading
+    Transient(String),
+    // not worth another try: a wrong type, bad auth, a script error, ...
+    Persistent(String),
+}
+
+impl fmt::Display for RsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RsError::Transient(e) => write!(f, "redis is unreachable: {}", e),
+            RsError::Persistent(e) => write!(f, "redis refused the command: {}", e),
+        }
+    }
+}
+
+pub type RsResult<T> = Result<T, RsError>;
+
+pub trait HelperStore: Send + Sync {
+    // a handle for one thread: cheap to make, and it may hold on to a connection until it is dropped
+    fn connect(&self) -> Box<dyn StoreConn>;
+    // for the log and error messages
+    fn describe(&self) -> String;
+}
+
+pub trait StoreConn {
+    // the handful of redis commands that the helper uses, with their redis meanings
+    //  (a missing key is an empty set, SPOP/GET/BLPOP say None when there is nothing, ...)
+    fn scard(&mut self, k: &str) -> RsResult<i32>;
+    fn spop(&mut self, k: &str) -> RsResult<Option<String>>;
+    fn sadd(&mut self, k: &str, v: &[String]) -> RsResult<()>;
+    fn smembers(&mut self, k: &str) -> RsResult<Vec<String>>;
+    fn get(&mut self, k: &str) -> RsResult<Option<String>>;
+    fn set(&mut self, k: &str, v: &str) -> RsResult<()>;
+    fn del(&mut self, k: &str) -> RsResult<i32>;
+    fn exists(&mut self, k: &str) -> RsResult<bool>;
+    fn rpush(&mut self, k: &str, v: &str) -> RsResult<()>;
+    fn blpop(&mut self, k: &str, timeout: usize) -> RsResult<Option<String>>;
+    fn ping(&mut self) -> RsResult<()>;
+}
+
+pub struct RsConn {
+    // one thread's handle on whichever store the pools were set up with
+    conn: Box<dyn StoreConn>,
+}
+
+impl RsConn {
+    pub fn new(pools: &DbPools) -> RsConn {
+        // nothing is checked out until the first command
+        RsConn { conn: pools.store().connect() }
+    }
+}
+
+pub fn rs_del(k: &str, c: &mut RsConn) -> RsResult<i32> {
+    // DEL: how many keys went (0 or 1); only one of two clients deleting the same key at once is told 1
+    c.conn.del(k)
+}
+
+pub fn rs_exists(k: &str, c: &mut RsConn) -> RsResult<bool> {
+    // EXISTS
+    c.conn.exists(k)
+}
+
+pub fn rs_scard(k: &str, c: &mut RsConn) -> RsResult<i32> {
+    // SCARD: a missing key is an empty set
+    c.conn.scard(k)
+}
+
+pub fn rs_spop(k: &str, c: &mut RsConn) -> RsResult<Option<String>> {
+    // SPOP: None means that the set is empty (or gone), i.e. there is no more work
+    //  NB: if the connection drops after redis popped but before we heard back, that item is lost: so SPOP is never retried
+    c.conn.spop(k)
+}
+
+pub fn rs_get(k: &str, c: &mut RsConn) -> RsResult<Option<String>> {
+    // GET: None means that the key is not there
+    c.conn.get(k)
+}
+
+pub fn rs_smembers(k: &str, c: &mut RsConn) -> RsResult<Vec<String>> {
+    // SMEMBERS
+    c.conn.smembers(k)
+}
+
+pub fn rs_sadd(k: &str, v: &str, c: &mut RsConn) -> RsResult<()> {
+    // SADD
+    c.conn.sadd(k, &[v.to_string()])
+}
+
+pub fn rs_saddmany(k: &str, v: &[String], c: &mut RsConn) -> RsResult<()> {
+    // SADD with many members in one round trip
+    if v.is_empty() {
+        return Ok(())
+    }
+    c.conn.sadd(k, v)
+}
+
+pub fn rs_set_str(k: &str, v: &str, c: &mut RsConn) -> RsResult<()> {
+    // SET
+    c.conn.set(k, v)
+}
+
+pub fn rs_set_int(k: &str, v: i32, c: &mut RsConn) -> RsResult<()> {
+    // SET
+    c.conn.set(k, &v.to_string())
+}
+
+pub fn rs_rpush(k: &str, v: &str, c: &mut RsConn) -> RsResult<()> {
+    // RPUSH: not retried, since a push that got through before the connection dropped would queue the job twice
+    c.conn.rpush(k, v)
+}
+
+pub fn rs_blpop(k: &str, timeout: usize, c: &mut RsConn) -> RsResult<Option<String>> {
+    // BLPOP: wait up to timeout seconds for something to arrive; None means that nothing did
+    //  NB: the same caveat as SPOP applies if the connection drops while the reply is in flight, and neither is retried
+    c.conn.blpop(k, timeout)
+}
+
+pub fn rs_ping(c: &mut RsConn) -> RsResult<()> {
+    // PING
+    c.conn.ping()
+}
+
+enum MemValue {
+    Text(String),
+    Set(HashSet<String>),
+    List(VecDeque<String>),
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    // everything in one HashMap; the Condvar wakes BLPOP when something is pushed
+    //  clones share the same data, so every thread (and every DbPools clone) sees the same store
+    data: Arc<(Mutex<HashMap<String, MemValue>>, Condvar)>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn wrongtype(k: &str) -> RsError {
+        RsError::Persistent(format!("WRONGTYPE Operation against a key holding the wrong kind of value: {}", k))
+    }
+}
+
+impl HelperStore for MemoryStore {
+    fn connect(&self) -> Box<dyn StoreConn> {
+        Box::new(self.clone())
+    }
+
+    fn describe(&self) -> String {
+        "memory://".to_string()
+    }
+}
+
+impl StoreConn for MemoryStore {
+    fn scard(&mut self, k: &str) -> RsResult<i32> {
+        match self.data.0.lock().unwrap().get(k) {
+            None => Ok(0),
+            Some(MemValue::Set(s)) => Ok(s.len() as i32),
+            Some(_) => Err(MemoryStore::wrongtype(k)),
+        }
+    }
+
+    fn spop(&mut self, k: &str) -> RsResult<Option<String>> {
+        let mut d = self.data.0.lock().unwrap();
+        let (popped, emptied) = match d.get_mut(k) {
+            None => return Ok(None),
+            Some(MemValue::Set(s)) => {
+                let v = s.iter().next().cloned();
+                if let Some(v) = &v {
+                    s.remove(v);
+                }
+                (v, s.is_empty())
+            }
+            Some(_) => return Err(MemoryStore::wrongtype(k)),
+        };
+        // like redis: an empty set is no key at all
+        if emptied {
+            d.remove(k);
+        }
+        Ok(popped)
+    }
+
+    fn sadd(&mut self, k: &str, v: &[String]) -> RsResult<()> {
+        let mut d = self.data.0.lock().unwrap();
+        match d.entry(k.to_string()).or_insert_with(|| MemValue::Set(HashSet::new())) {
+            MemValue::Set(s) => {
+                s.extend(v.iter().cloned());
+                Ok(())
+            }
+            _ => Err(MemoryStore::wrongtype(k)),
+        }
+    }
+
+    fn smembers(&mut self, k: &str) -> RsResult<Vec<String>> {
+        match self.data.0.lock().unwrap().get(k) {
+            None => Ok(Vec::new()),
+            Some(MemValue::Set(s)) => Ok(s.iter().cloned().collect()),
+            Some(_) => Err(MemoryStore::wrongtype(k)),
+        }
+    }
+
+    fn get(&mut self, k: &str) -> RsResult<Option<String>> {
+        match self.data.0.lock().unwrap().get(k) {
+            None => Ok(None),
+            Some(MemValue::Text(t)) => Ok(Some(t.clone())),
+            Some(_) => Err(MemoryStore::wrongtype(k)),
+        }
+    }
+
+    fn set(&mut self, k: &str, v: &str) -> RsResult<()> {
+        // SET replaces whatever was there, whatever it was
+        self.data.0.lock().unwrap().insert(k.to_string(), MemValue::Text(v.to_string()));
+        Ok(())
+    }
+
+    fn del(&mut self, k: &str) -> RsResult<i32> {
+        Ok(self.data.0.lock().unwrap().remove(k).map_or(0, |_| 1))
+    }
+
+    fn exists(&mut self, k: &str) -> RsResult<bool> {
+        Ok(self.data.0.lock().unwrap().contains_key(k))
+    }
+
+    fn rpush(&mut self, k: &str, v: &str) -> RsResult<()> {
+        let mut d = self.data.0.lock().unwrap();
+        match d.entry(k.to_string()).or_insert_with(|| MemValue::List(VecDeque::new())) {
+            MemValue::List(l) => l.push_back(v.to_string()),
+            _ => return Err(MemoryStore::wrongtype(k)),
+        }
+        self.data.1.notify_all();
+        Ok(())
+    }
+
+    fn blpop(&mut self, k: &str, timeout: usize) -> RsResult<Option<String>> {
+        // as in redis, a timeout of 0 waits for ever
+        let deadline = match timeout {
+            0 => None,
+            t => Some(Instant::now() + Duration::from_secs(t as u64)),
+        };
+        let mut d = self.data.0.lock().unwrap();
+        loop {
+            let (popped, emptied) = match d.get_mut(k) {
+                Some(MemValue::List(l)) => {
+                    let v = l.pop_front();
+                    (v, l.is_empty())
+                }
+                Some(_) => return Err(MemoryStore::wrongtype(k)),
+                None => (None, false),
+            };
+            if emptied {
+                d.remove(k);
+            }
+            if popped.is_some() {
+                return Ok(popped)
+            }
+            d = match deadline {
+                None => self.data.1.wait(d).unwrap(),
+                Some(t) => {
+                    let now = Instant::now();
+                    if now >= t {
+                        return Ok(None)
+                    }
+                    self.data.1.wait_timeout(d, t - now).unwrap().0
+                }
+            };
+        }
+    }
+
+    fn ping(&mut self) -> RsResult<()> {
+        Ok(())
+    }
+}
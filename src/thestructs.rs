@@ -3,6 +3,7 @@
 //    License: GNU GENERAL PUBLIC LICENSE 3
 //        (see LICENSE in the top level directory of the distribution)
 
+#[allow(dead_code)]
 pub struct DBLine {
     pub idx: i32,
     pub uid: String,
@@ -20,6 +21,7 @@ pub struct DBLine {
 }
 
 #[derive(Clone)]
+#[allow(dead_code)]
 pub struct DbMorphology {
     pub obs: String,
     pub xrf: String,
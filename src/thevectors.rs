@@ -4,239 +4,388 @@
 //
 
 use std::collections::HashMap;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use humantime::format_duration;
+use log::{debug, info};
 use regex::Regex;
 
 use crate::dbfunctions::*;
-use crate::helpers::*;
+use crate::thecompat::*;
+use crate::thecorpus::*;
 use crate::svfunctions::*;
+use crate::theerrors::*;
+use crate::thelogging::*;
+use crate::themetrics::*;
+use crate::thepools::*;
+use crate::thestorage::*;
 use crate::thestructs::*;
 
 static SKIPHEADWORDS: &str = "unus verum omne sum¹ ab δύο πρότεροϲ ἄνθρωποϲ τίϲ δέω¹ ὅϲτιϲ homo πᾶϲ οὖν εἶπον ἠμί ἄν² tantus μένω μέγαϲ οὐ verus neque eo¹ nam μέν ἡμόϲ aut Sue διό reor ut ἐγώ is πωϲ ἐκάϲ enim ὅτι² παρά ἐν Ἔχιϲ sed ἐμόϲ οὐδόϲ ad de ita πηρόϲ οὗτοϲ an ἐπεί a γάρ αὐτοῦ ἐκεῖνοϲ ἀνά ἑαυτοῦ quam αὐτόϲε et ὑπό quidem Alius¹ οἷοϲ noster γίγνομαι ἄνα προϲάμβ ἄν¹ οὕτωϲ pro² tamen ἐάν atque τε qui² si multus idem οὐδέ ἐκ omnes γε causa δεῖ πολύϲ in ἔδω ὅτι¹ μή Ios ἕτεροϲ cum meus ὅλοξ suus omnis ὡϲ sua μετά Ἀλλά ne¹ jam εἰϲ ἤ² ἄναξ ἕ ὅϲοϲ dies ipse ὁ hic οὐδείϲ suo ἔτι ἄνω¹ ὅϲ νῦν ὁμοῖοϲ edo¹ εἰ qui¹ πάλιν ὥϲπερ ne³ ἵνα τιϲ διά φύω per τοιοῦτοϲ for eo² huc locum neo¹ sui non ἤ¹ χάω ex κατά δή ἁμόϲ dico² ὅμοιοϲ αὐτόϲ etiam vaco πρόϲ Ζεύϲ ϲύ quis¹ tuus b εἷϲ Eos οὔτε τῇ καθά ego tu ille pro¹ ἀπό suum εἰμί ἄλλοϲ δέ alius² pars vel ὥϲτε χέω res ἡμέρα quo δέομαι modus ὑπέρ ϲόϲ ito τῷ περί Τήιοϲ ἕκαϲτοϲ autem καί ἐπί nos θεάω γάρον γάροϲ Cos²";
 static SKIPINFLECTED: &str = "ita a inquit ego die nunc nos quid πάντων ἤ με θεόν δεῖ for igitur ϲύν b uers p ϲου τῷ εἰϲ ergo ἐπ ὥϲτε sua me πρό sic aut nisi rem πάλιν ἡμῶν φηϲί παρά ἔϲτι αὐτῆϲ τότε eos αὐτούϲ λέγει cum τόν quidem ἐϲτιν posse αὐτόϲ post αὐτῶν libro m hanc οὐδέ fr πρῶτον μέν res ἐϲτι αὐτῷ οὐχ non ἐϲτί modo αὐτοῦ sine ad uero fuit τοῦ ἀπό ea ὅτι parte ἔχει οὔτε ὅταν αὐτήν esse sub τοῦτο i omnes break μή ἤδη ϲοι sibi at mihi τήν in de τούτου ab omnia ὃ ἦν γάρ οὐδέν quam per α autem eius item ὡϲ sint length οὗ eum ἀντί ex uel ἐπειδή re ei quo ἐξ δραχμαί αὐτό ἄρα ἔτουϲ ἀλλ οὐκ τά ὑπέρ τάϲ μάλιϲτα etiam haec nihil οὕτω siue nobis si itaque uac erat uestig εἶπεν ἔϲτιν tantum tam nec unde qua hoc quis iii ὥϲπερ semper εἶναι e ½ is quem τῆϲ ἐγώ καθ his θεοῦ tibi ubi pro ἄν πολλά τῇ πρόϲ l ἔϲται οὕτωϲ τό ἐφ ἡμῖν οἷϲ inter idem illa n se εἰ μόνον ac ἵνα ipse erit μετά μοι δι γε enim ille an sunt esset γίνεται omnibus ne ἐπί τούτοιϲ ὁμοίωϲ παρ causa neque cr ἐάν quos ταῦτα h ante ἐϲτίν ἣν αὐτόν eo ὧν ἐπεί οἷον sed ἀλλά ii ἡ t te ταῖϲ est sit cuius καί quasi ἀεί o τούτων ἐϲ quae τούϲ minus quia tamen iam d διά primum r τιϲ νῦν illud u apud c ἐκ δ quod f quoque tr τί ipsa rei hic οἱ illi et πῶϲ φηϲίν τοίνυν s magis unknown οὖν dum text μᾶλλον habet τοῖϲ qui αὐτοῖϲ suo πάντα uacat τίϲ pace ἔχειν οὐ κατά contra δύο ἔτι αἱ uet οὗτοϲ deinde id ut ὑπό τι lin ἄλλων τε tu ὁ cf δή potest ἐν eam tum μου nam θεόϲ κατ ὦ cui nomine περί atque δέ quibus ἡμᾶϲ τῶν eorum";
 static TERMINATIONS: &str = ".?!;·";
 
-pub fn vector_prep(thekey: &str, b: &str, _workers: i32, bagsize: i32, db: &str, s: i32, e: i32, ll: i32, psq: &str, rca: &str) -> String {
-    // VECTOR PREP builds bags for modeling; to do this you need to...
-    //
-    // [a] grab db lines that are relevant to the search
-    // [b] turn them into a unified text block
-    // [c] do some preliminary cleanups
-    // [d] break the text into sentences and assemble []SentenceWithLocus (NB: these are "unlemmatized bags of words")
-    // [e] figure out all of the words used in the passage
-    // [f] find all of the parsing info relative to these words
-    // [g] figure out which headwords to associate with the collection of words
-    // [h] build the lemmatized bags of words ('unlemmatized' can skip [f] and [g]...)
-    // [i] store the bags
-    //
-    // once you reach this point python can fetch the bags and then run "Word2Vec(bags, parameters, ...)"
-    //
+// the bagging methods that BagBuild knows ('unlemmatized' is not one of them yet)
+static BAGMETHODS: [&str; 3] = ["flat", "alternates", "winnertakesall"];
+
+pub struct BagState {
+    // what each stage leaves for the ones after it
+    pub key: String,
+    pub lines: Vec<DBLine>,
+    pub text: String,
+    // locus --> sentence (and then locus --> bag)
+    pub sentences: HashMap<String, String>,
+    pub words: Vec<String>,
+    pub morphology: Vec<DbMorphology>,
+    pub morphmap: HashMap<String, Vec<String>>,
+    pub bags: HashMap<String, String>,
+    pub resultkey: String,
+    // (label, what the stage said, start.elapsed() when it ended) for each stage that ran
+    pub laps: Vec<(String, String, Duration)>,
+}
 
-    // https://doc.rust-lang.org/std/time/struct.SystemTime.html
-    let start = Instant::now();
+impl BagState {
+    pub fn new(key: &str) -> BagState {
+        BagState {
+            key: key.to_string(),
+            lines: Vec::new(),
+            text: String::new(),
+            sentences: HashMap::new(),
+            words: Vec::new(),
+            morphology: Vec::new(),
+            morphmap: HashMap::new(),
+            bags: HashMap::new(),
+            resultkey: String::new(),
+            laps: Vec::new(),
+        }
+    }
+}
 
-    let m = format!("Seeking to build {} bags of words", &b);
-    lfl(m, ll, 1);
+pub struct BagContext<'a> {
+    // the connections that the stages share for the length of one run
+    pub db: &'a mut dyn CorpusConn,
+    pub rc: &'a mut RsConn,
+    // how BagStore writes the bags: see thecompat.rs
+    pub format: HelperFormat,
+}
+
+pub trait BagStage {
+    // "A", "B", ...: the label in the debug log and in the hrh_vector_stage_seconds histogram
+    fn label(&self) -> &str;
+    // do the work and say what was done (e.g., "found 12 sentences")
+    fn run(&self, st: &mut BagState, cx: &mut BagContext) -> HelperResult<String>;
+}
 
-    let mut rc = redisconnect(rca.to_string());
-    let mut pg = postgresconnect(psq.to_string());
+#[derive(Default)]
+pub struct BagPipeline {
+    stages: Vec<Box<dyn BagStage>>,
+}
 
-    // turn of progress logging
-    let thiskey = format!("{}_poolofwork", &thekey);
-    rs_set_int(&thiskey, -1, &mut rc).unwrap();
-    let thiskey = format!("{}_hitcount", &thekey);
-    rs_set_int(&thiskey, 0, &mut rc).unwrap();
+impl BagPipeline {
+    pub fn standard(b: &str, bagsize: i32, db: &str, s: i32, e: i32, stops: (Option<String>, Option<String>)) -> HelperResult<BagPipeline> {
+        // VECTOR PREP builds bags for modeling; to do this you need to...
+        //
+        // [a] grab db lines that are relevant to the search
+        // [b] turn them into a unified text block
+        // [c] do some preliminary cleanups
+        // [d] break the text into sentences and assemble []SentenceWithLocus (NB: these are "unlemmatized bags of words")
+        // [e] figure out all of the words used in the passage
+        // [f] find all of the parsing info relative to these words
+        // [g] figure out which headwords to associate with the collection of words
+        // [h] build the lemmatized bags of words ('unlemmatized' can skip [f] and [g]...)
+        // [i] purge the stopwords
+        // [j] store the bags
+        //
+        // once you reach this point python can fetch the bags and then run "Word2Vec(bags, parameters, ...)"
+        //
+
+        // unlemmatized bags of words customers have in fact reached their target as of now
+        //  (dropstopwords, loadthebags, print the result key): not yet supported
+        bagmethod(b)?;
+
+        //  (a config file or --svsh/--svsi can swap in other lists)
+        let (headwords, inflected) = stops;
+        let mut p = BagPipeline::default();
+        p.push(Box::new(BagFetch { db: db.to_string(), start: s, end: e }))
+            .push(Box::new(BagUnify))
+            .push(Box::new(BagClean))
+            .push(Box::new(BagSentences { bagsize }))
+            .push(Box::new(BagWords))
+            .push(Box::new(BagMorphology))
+            .push(Box::new(BagMorphmap))
+            .push(Box::new(BagBuild { method: b.to_string() }))
+            .push(Box::new(BagStopwords {
+                headwords: headwords.unwrap_or_else(|| SKIPHEADWORDS.to_string()),
+                inflected: inflected.unwrap_or_else(|| SKIPINFLECTED.to_string()),
+            }))
+            .push(Box::new(BagStore));
+        Ok(p)
+    }
 
-    // [a] grab the db lines
-    if &thekey == &"rusttest" {
-        let m = format!("No redis key; gathering lines with a direct CLI PostgreSQL query)");
-        lfl(m, ll, 1);
-        // otherwise we will mimic grabworker() pattern to aggregate the lines
+    pub fn push(&mut self, stage: Box<dyn BagStage>) -> &mut BagPipeline {
+        self.stages.push(stage);
+        self
     }
 
-    let dblines: Vec<DBLine> = match &thekey {
-        // either db_directfetch()
-        // otherwise we will mimic grabworker() pattern to aggregate the lines
-        &"rusttest" => db_directfetch(db, s, e, &mut pg),
-        _ => db_redisfectch(&thekey, psq, rca, ),
-    };
+    pub fn replace(&mut self, label: &str, stage: Box<dyn BagStage>) -> HelperResult<&mut BagPipeline> {
+        // e.g., swap "H" for a bagger of your own
+        let i = self.position(label)?;
+        self.stages[i] = stage;
+        Ok(self)
+    }
 
-    let duration = start.elapsed();
-    let m = format!("{} dblines fetched [A: {}]", dblines.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+    pub fn insert_after(&mut self, label: &str, stage: Box<dyn BagStage>) -> HelperResult<&mut BagPipeline> {
+        // e.g., an extra cleanup after "C"
+        let i = self.position(label)?;
+        self.stages.insert(i + 1, stage);
+        Ok(self)
+    }
 
-    // [b] turn them into a unified text block
+    pub fn remove(&mut self, label: &str) -> HelperResult<&mut BagPipeline> {
+        let i = self.position(label)?;
+        self.stages.remove(i);
+        Ok(self)
+    }
 
-    let txtlines: Vec<String> = dblines.iter()
-        .map(|x| format!{"⊏line/{}/{}⊐{}", x.uid, x.idx, x.mu})
-        .collect();
+    pub fn labels(&self) -> Vec<&str> {
+        self.stages.iter().map(|s| s.label()).collect()
+    }
 
-    let fulltext: String = txtlines.join(" ");
-    // println!("{}", fulltext);
+    fn position(&self, label: &str) -> HelperResult<usize> {
+        self.stages.iter().position(|s| s.label() == label)
+            .ok_or_else(|| HelperError::UnsupportedMode(format!("there is no stage '{}' in this pipeline: {}", label, self.labels().join(", "))))
+    }
 
-    let duration = start.elapsed();
-    let m = format!("unified text block built [B: {}]", format_duration(duration).to_string());
-    lfl(m, ll, 2);
+    pub fn run(&self, thekey: &str, pools: &DbPools) -> HelperResult<String> {
+        // the key that the bags were stored under
+        self.execute(thekey, pools).map(|st| st.resultkey)
+    }
 
-    // [c] do some preliminary cleanups
-    // parsevectorsentences()
+    pub fn execute(&self, thekey: &str, pools: &DbPools) -> HelperResult<BagState> {
+        // every stage in turn, with the time since the start logged (and each stage's own time counted) as it ends
+        //  whatever the last stage left behind is handed back (e.g., the bags themselves if "J" was removed)
+        // https://doc.rust-lang.org/std/time/struct.SystemTime.html
+        let start = Instant::now();
+        let mut lap = Duration::ZERO;
+        lg_setkey(thekey);
+        mt_count(MtCounter::VectorRuns);
+
+        let mut rc = RsConn::new(pools);
+        let mut db = CorpusGuard::open(pools.corpus().as_ref())?;
+
+        // turn of progress logging
+        let thiskey = format!("{}_poolofwork", &thekey);
+        rs_set_int(&thiskey, -1, &mut rc)?;
+        let thiskey = format!("{}_hitcount", &thekey);
+        rs_set_int(&thiskey, 0, &mut rc)?;
+
+        let mut st = BagState::new(thekey);
+        let mut cx = BagContext { db: &mut *db, rc: &mut rc, format: pools.format() };
+        for s in &self.stages {
+            let said = s.run(&mut st, &mut cx)?;
+            let duration = start.elapsed();
+            debug!("{} [{}: {}]", said, s.label(), format_duration(duration));
+            mt_lap(s.label(), duration, &mut lap);
+            st.laps.push((s.label().to_string(), said, duration));
+        }
 
-    let strip = vec!["&nbsp;", "- ", "<.*?>"];
-    let re_array: Vec<Regex> = strip.iter().map(|x| Regex::new(x).unwrap()).collect();
-    let fulltext = sv_stripper(fulltext.as_str(), re_array);
-    let fulltext = sv_acuteforgrave(fulltext);
-    // println!("stripped\n{}", fulltext);
-
-    let re = Regex::new("v").unwrap();
-    let fulltext = re.replace_all(&*fulltext, "u");
-    let re = Regex::new("j").unwrap();
-    let fulltext = re.replace_all(&*fulltext, "i");
-    let re = Regex::new("[σς]").unwrap();
-    let fulltext = re.replace_all(&*fulltext, "ϲ");
-    let fulltext = sv_swapper(&fulltext);
-    // println!("{}", fulltext);
-
-    let duration = start.elapsed();
-    let m = format!("preliminary cleanups complete [C: {}]", format_duration(duration).to_string());
-    lfl(m, ll, 2);
+        db.finish()?;
+        Ok(st)
+    }
+}
 
-    // [d] break the text into sentences and assemble SentencesWithLocus
+#[allow(clippy::too_many_arguments)]
+pub fn vector_prep(thekey: &str, b: &str, _workers: i32, bagsize: i32, db: &str, s: i32, e: i32, stops: (Option<String>, Option<String>), pools: &DbPools) -> HelperResult<String> {
+    // the standard pipeline, as the CLI, the daemon and the HTTP API run it
+    lg_setkey(thekey);
+    info!("Seeking to build {} bags of words", &b);
+    // only the "rusttest" key reads --svdb from --svs to --sve
+    let db = if thekey == "rusttest" { db } else { "" };
+    BagPipeline::standard(b, bagsize, db, s, e, stops)?.run(thekey, pools)
+}
+
+fn bagmethod(b: &str) -> HelperResult<()> {
+    if BAGMETHODS.contains(&b) {
+        Ok(())
+    } else {
+        Err(HelperError::UnsupportedMode(format!("'{}' bags of words are not supported", b)))
+    }
+}
 
-    // from the .split() documentation:
-    // If the pattern is a slice of chars, split on each occurrence of any of the characters:
-    // let v: Vec<&str> = "2020-11-03 23:59".split(&['-', ' ', ':', '@'][..]).collect();
-    // assert_eq!(v, ["2020", "11", "03", "23", "59"]);
+pub struct BagFetch {
+    // [a] grab the db lines: lines start through end of db, or (if there is no db) the work items under the key
+    pub db: String,
+    pub start: i32,
+    pub end: i32,
+}
 
-    let terminations: Vec<char> = TERMINATIONS.chars().collect();
-    let splittext: Vec<&str> = fulltext.split(&terminations[..]).collect();
+impl BagStage for BagFetch {
+    fn label(&self) -> &str { "A" }
+
+    fn run(&self, st: &mut BagState, cx: &mut BagContext) -> HelperResult<String> {
+        st.lines = match self.db.as_str() {
+            // either db_directfetch() (or whatever the corpus does instead)
+            // otherwise we will mimic grabworker() pattern to aggregate the lines
+            "" => db_redisfectch(&st.key, cx.db, cx.rc)?,
+            _ => {
+                info!("No redis key; gathering lines with a direct CLI PostgreSQL query)");
+                cx.db.lines(&self.db, self.start, self.end)?
+            }
+        };
+        Ok(format!("{} dblines fetched", st.lines.len()))
+    }
+}
 
-    // alternate splitter: no real speed difference
+pub struct BagUnify;
 
-    // let re = Regex::new("[?!;·]").unwrap();
-    // let fulltext = re.replace_all(&*fulltext, ".");
-    // let splittext: Vec<&str> = fulltext.split(".").collect();
+impl BagStage for BagUnify {
+    // [b] turn them into a unified text block
+    fn label(&self) -> &str { "B" }
+
+    fn run(&self, st: &mut BagState, _cx: &mut BagContext) -> HelperResult<String> {
+        let txtlines: Vec<String> = st.lines.iter()
+            .map(|x| format!{"⊏line/{}/{}⊐{}", x.uid, x.idx, x.mu})
+            .collect();
+        st.text = txtlines.join(" ");
+        Ok("unified text block built".to_string())
+    }
+}
 
-    let sentenceswithlocus: HashMap<String, String> = sv_buildsentences(splittext, bagsize);
+pub struct BagClean;
 
-    // for (key, value) in &sentenceswithlocus {
-    //     let m = format!("{}: {}", key, value);
-    //     lfl(m, 0, 0);
-    // }
+impl BagStage for BagClean {
+    // [c] do some preliminary cleanups
+    // parsevectorsentences()
+    fn label(&self) -> &str { "C" }
+
+    fn run(&self, st: &mut BagState, _cx: &mut BagContext) -> HelperResult<String> {
+        let strip = ["&nbsp;", "- ", "<.*?>"];
+        let re_array: Vec<Regex> = strip.iter().map(|x| Regex::new(x).unwrap()).collect();
+        let fulltext = sv_stripper(st.text.as_str(), re_array);
+        let fulltext = sv_acuteforgrave(fulltext);
+
+        let re = Regex::new("v").unwrap();
+        let fulltext = re.replace_all(&fulltext, "u");
+        let re = Regex::new("j").unwrap();
+        let fulltext = re.replace_all(&fulltext, "i");
+        let re = Regex::new("[σς]").unwrap();
+        let fulltext = re.replace_all(&fulltext, "ϲ");
+        st.text = sv_swapper(&fulltext);
+        Ok("preliminary cleanups complete".to_string())
+    }
+}
 
-    let duration = start.elapsed();
-    let m = format!("found {} sentences [D: {}]", sentenceswithlocus.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+pub struct BagSentences {
+    pub bagsize: i32,
+}
 
-    // unlemmatized bags of words customers have in fact reached their target as of now
-    if &b == &"unlemmatized" {
-        // dropstopwords
-        // loadthebags
-        // print the result key
-        println!("unlemmatized bags of words not yet supported");
-        std::process::exit(1);
+impl BagStage for BagSentences {
+    // [d] break the text into sentences and assemble SentencesWithLocus
+    fn label(&self) -> &str { "D" }
+
+    fn run(&self, st: &mut BagState, _cx: &mut BagContext) -> HelperResult<String> {
+        // from the .split() documentation:
+        // If the pattern is a slice of chars, split on each occurrence of any of the characters:
+        // let v: Vec<&str> = "2020-11-03 23:59".split(&['-', ' ', ':', '@'][..]).collect();
+        // assert_eq!(v, ["2020", "11", "03", "23", "59"]);
+        let terminations: Vec<char> = TERMINATIONS.chars().collect();
+        let splittext: Vec<&str> = st.text.split(&terminations[..]).collect();
+        st.sentences = sv_buildsentences(splittext, self.bagsize);
+        Ok(format!("found {} sentences", st.sentences.len()))
     }
+}
+
+pub struct BagWords;
 
+impl BagStage for BagWords {
     // [e] figure out all of the words used in the passage
+    fn label(&self) -> &str { "E" }
 
-    let sentences: Vec<&str> = sentenceswithlocus.keys().map(|x| sentenceswithlocus[x].as_str()).collect();
-    let allwords: Vec<&str> = sv_findallwords(sentences);
+    fn run(&self, st: &mut BagState, _cx: &mut BagContext) -> HelperResult<String> {
+        let sentences: Vec<&str> = st.sentences.values().map(|x| x.as_str()).collect();
+        st.words = sv_findallwords(sentences).into_iter().map(|w| w.to_string()).collect();
+        Ok(format!("found {} words", st.words.len()))
+    }
+}
 
-    let duration = start.elapsed();
-    let m = format!("found {} words [E: {}]", allwords.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+pub struct BagMorphology;
 
+impl BagStage for BagMorphology {
     // [f] find all of the parsing info relative to these words
+    fn label(&self) -> &str { "F" }
 
-    let  mo: Vec<DbMorphology> = sv_getrequiredmorphobjects(allwords.clone(), &mut pg);
-
-    // note that you will have more in [f] than in [g]; but the golang version is a map and
-    // so there len(f) = len(g); nevertheless len(e) is supposed to match len(g) in both cases
-    // since we refuse to drop any words
+    fn run(&self, st: &mut BagState, cx: &mut BagContext) -> HelperResult<String> {
+        let words: Vec<&str> = st.words.iter().map(|w| w.as_str()).collect();
+        st.morphology = sv_getrequiredmorphobjects(words, cx.db)?;
+        Ok(format!("found {} morphology objects", st.morphology.len()))
+    }
+}
 
-    let duration = start.elapsed();
-    let m = format!("found {} morphology objects [F: {}]", mo.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+pub struct BagMorphmap;
 
+impl BagStage for BagMorphmap {
     // [g] figure out which headwords to associate with the collection of words
-    // see convertmophdicttodict()
-    // a set of sets
-    //	key = word-in-use
-    //	value = { maybeA, maybeB, maybeC}
-    // {'θεῶν': {'θεόϲ', 'θέα', 'θεάω', 'θεά'}, 'πώ': {'πω'}, 'πολλά': {'πολύϲ'}, 'πατήρ': {'πατήρ'}, ... }
-
-    let mut morphmap: HashMap<String, Vec<String>> = HashMap::new();
-    for m in mo {
-        morphmap.insert(m.obs, m.upo);
-    }
-
-    // retain unparsed terms
-    for w in allwords {
-        if morphmap.contains_key(w) {
-            ()
-        } else {
-            morphmap.insert(w.to_string(), vec![w.to_string()]);
-        }
-    }
+    fn label(&self) -> &str { "G" }
 
-    // note that capitalization issues mean that your morphmap can be longer than the total number of words
+    fn run(&self, st: &mut BagState, _cx: &mut BagContext) -> HelperResult<String> {
+        let words: Vec<&str> = st.words.iter().map(|w| w.as_str()).collect();
+        st.morphmap = sv_buildmorphmap(std::mem::take(&mut st.morphology), &words);
+        Ok(format!("Built morphmap for {} items", st.morphmap.len()))
+    }
+}
 
-    let duration = start.elapsed();
-    let m = format!("Built morphmap for {} items [G: {}]", morphmap.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+pub struct BagBuild {
+    // flat, alternates, or winnertakesall
+    pub method: String,
+}
 
+impl BagStage for BagBuild {
     // [h] build the lemmatized bags of words
+    fn label(&self) -> &str { "H" }
+
+    fn run(&self, st: &mut BagState, cx: &mut BagContext) -> HelperResult<String> {
+        // no: the baggers really should retain the locus info; this will prevent collisions in the redis set
+        // TODO / IN PROGRESS
+        bagmethod(&self.method)?;
+        let sentences = std::mem::take(&mut st.sentences);
+        let morphmap = std::mem::take(&mut st.morphmap);
+        st.bags = match self.method.as_str() {
+            "flat" => sv_buildflatbags(sentences, morphmap),
+            "alternates" => sv_buildcompositebags(sentences, morphmap),
+            _ => sv_buildwinnertakesallbags(sentences, morphmap, cx.db)?,
+        };
+        Ok(format!("Built {} bags", st.bags.len()))
+    }
+}
 
-    // no: the baggers really should retain the locus info; this will prevent collisions in the redis set
-    // TODO / IN PROGRESS
-
-    let bagged: HashMap<String, String>  = match b {
-        "flat" => sv_buildflatbags(sentenceswithlocus.to_owned(), morphmap),
-        "alternates" => sv_buildcompositebags(sentenceswithlocus.to_owned(), morphmap),
-        "winnertakesall" => sv_buildwinnertakesallbags(sentenceswithlocus.to_owned(), morphmap, &mut pg),
-        // should never hit this but...
-        _ => sentenceswithlocus,
-    };
-
-    // for b in &bagged {
-    //     if b.len() > 0 {
-    //         println!("[bag] {}", &b);
-    //     }
-    // }
-
-    let duration = start.elapsed();
-    let m = format!("Built {} bags [H: {}]", bagged.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+pub struct BagStopwords {
+    // space-separated lists
+    pub headwords: String,
+    pub inflected: String,
+}
 
+impl BagStage for BagStopwords {
     // [i] purge stopwords
-
-    let bagged: HashMap<String, String> = sv_dropstopwords(SKIPHEADWORDS, bagged);
-    let bagged: HashMap<String, String> = sv_dropstopwords(SKIPINFLECTED, bagged);
-
-    // no empty bags...
-    let mut bags: HashMap<String, String> = HashMap::new();
-    for b in bagged.keys() {
-        let thisbag = bagged[b].clone();
-        if thisbag.len() > 0 {
-            // println!("[bag] {}", &b);
-            bags.insert(b.clone(), thisbag);
-        }
+    fn label(&self) -> &str { "I" }
+
+    fn run(&self, st: &mut BagState, _cx: &mut BagContext) -> HelperResult<String> {
+        let bagged = sv_dropstopwords(&self.headwords, std::mem::take(&mut st.bags));
+        let bagged = sv_dropstopwords(&self.inflected, bagged);
+        // no empty bags...
+        st.bags = bagged.into_iter().filter(|(_, b)| !b.is_empty()).collect();
+        Ok(format!("Purged stopwords in {} bags", st.bags.len()))
     }
+}
 
-    let duration = start.elapsed();
-    let m = format!("Purged stopwords in {} bags [I: {}]", bags.len(), format_duration(duration).to_string());
-    lfl(m, ll, 2);
+pub struct BagStore;
 
+impl BagStage for BagStore {
     // [j] store...
+    fn label(&self) -> &str { "J" }
 
-    let resultkey = format!("{}_vectorresults", &thekey);
-    let bl = bags.len();
-    sv_loadthebags(resultkey.clone(), bags, rca);
-
-    let duration = start.elapsed();
-    let m = format!("Stored {} bags [J: {}]", bl, format_duration(duration).to_string());
-    lfl(m, ll, 2);
-
-    resultkey
+    fn run(&self, st: &mut BagState, cx: &mut BagContext) -> HelperResult<String> {
+        st.resultkey = format!("{}_vectorresults", &st.key);
+        sv_loadthebags(&st.resultkey, &st.bags, cx.format, cx.rc)?;
+        Ok(format!("Stored {} bags", st.bags.len()))
+    }
 }
@@ -3,22 +3,154 @@
 //    License: GNU GENERAL PUBLIC LICENSE 3
 //        (see LICENSE in the top level directory of the distribution)
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::thread::spawn;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use hmac::{Hmac, Mac};
 use json::JsonValue;
-use tungstenite::{accept_hdr, handshake::server::{Request, Response}, Message, WebSocket};
+use log::{debug, info, trace, warn};
+use native_tls::{Identity, TlsAcceptor, TlsStream};
+use sha2::Sha256;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use subtle::ConstantTimeEq;
+use tungstenite::{accept_hdr, Message, WebSocket};
+use tungstenite::stream::Stream;
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::{Method, StatusCode, Version};
 
-use crate::dbfunctions::*;
 use crate::helpers::*;
+use crate::thecompat::*;
+use crate::theerrors::*;
+use crate::thelogging::*;
+use crate::themetrics::*;
+use crate::thepools::*;
+use crate::thestorage::*;
 
 static POLLINGINTERVAL: Duration = Duration::from_millis(400);
+static SCHEMAVERSION: i32 = 2;
+static HEADTIMEOUT: Duration = Duration::from_secs(10);
+static MAXHEADSIZE: usize = 16384;
 
-pub fn websocket(ft: &str, ll: i32, ip: &str, port: &str, save: i32, rc: String) {
+// either ws:// or wss://
+type WsStream = Stream<TcpStream, TlsStream<TcpStream>>;
+
+#[derive(Clone)]
+pub struct WsGatekeeper {
+    // who is allowed to open a socket: see ws_checkhandshake()
+    pub origins: Vec<String>,
+    pub mode: String,
+    pub secret: String,
+    pub pools: DbPools,
+}
+
+impl Callback for WsGatekeeper {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        match ws_checkhandshake(&self, request) {
+            Ok(()) => Ok(response),
+            Err(why) => {
+                info!("WebSocket handshake rejected: {}", why);
+                let mut refusal = ErrorResponse::new(Some(format!("403 Forbidden: {}", why)));
+                *refusal.status_mut() = StatusCode::FORBIDDEN;
+                Err(refusal)
+            }
+        }
+    }
+}
+
+pub struct WsSettings {
+    pub failthreshold: u32,
+    pub ip: String,
+    pub port: String,
+    pub save: i32,
+    pub schema: i32,
+    pub hitstostream: usize,
+    pub pools: DbPools,
+    pub tls: Option<(String, String)>,
+}
+
+#[derive(Default)]
+struct WsSubscription {
+    iterations: u32,
+    clients: u32,
+}
+
+// search ID -> who is watching it; every connection shares this so that /status can report on them
+type WsRegistry = Arc<Mutex<HashMap<String, WsSubscription>>>;
+
+struct WsWatcher {
+    // a client's place in the registry; dropping it (even via a panic) gives the place up
+    registry: WsRegistry,
+    rediskey: String,
+}
+
+impl WsWatcher {
+    fn new(registry: &WsRegistry, rediskey: &str) -> WsWatcher {
+        registry.lock().unwrap().entry(rediskey.to_string()).or_default().clients += 1;
+        WsWatcher { registry: registry.clone(), rediskey: rediskey.to_string() }
+    }
+
+    fn tick(&self, iterations: u32) {
+        if let Some(s) = self.registry.lock().unwrap().get_mut(&self.rediskey) {
+            s.iterations = s.iterations.max(iterations);
+        }
+    }
+}
+
+impl Drop for WsWatcher {
+    fn drop(&mut self) {
+        let mut r = match self.registry.lock() {
+            Ok(r) => r,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let gone = match r.get_mut(&self.rediskey) {
+            Some(s) => {
+                s.clients -= 1;
+                s.clients == 0
+            }
+            None => false,
+        };
+        if gone {
+            r.remove(&self.rediskey);
+        }
+    }
+}
+
+struct WsReplay {
+    // we had to read the request head to see if this was a websocket upgrade or a plain GET;
+    // hand those bytes back to tungstenite before reading anything new from the socket
+    head: Cursor<Vec<u8>>,
+    inner: WsStream,
+}
+
+impl Read for WsReplay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if (self.head.position() as usize) < self.head.get_ref().len() {
+            self.head.read(buf)
+        } else {
+            self.inner.read(buf)
+        }
+    }
+}
+
+impl Write for WsReplay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn websocket(settings: WsSettings, gate: WsGatekeeper) -> HelperResult<()> {
     //  WEBSOCKETS broadcasts search information for web page updates
     //
     //	[a] it launches and starts listening on a port
@@ -27,104 +159,462 @@ pub fn websocket(ft: &str, ll: i32, ip: &str, port: &str, save: i32, rc: String)
     //	[d] it parses, packages (as JSON), and then redistributes this information back over the websocket
     //	[e] when the poll disappears from redis, the messages stop broadcasting
     //
+    //  schema 1 re-sends every field as a string on every tick (this is what HipparchiaServer expects)
+    //  schema 2 sends one typed snapshot and then only the fields that changed: see ws_typedresults()
+    //
+    //  if hitstostream > 0, the first N hits that grabworker() stores in "<key>_results" also get pushed
+    //  out as they arrive: {"Type": "hit", "ID": "ebf24e19", "Hit": {"WkUID": ..., "TbIndex": ..., ...}}
+    //
+    //  plain HTTP GETs on the same port are answered too: see ws_http()
+    //
 
     // INCOMPLETE relative to the golang version
     // still missing:
     // deletewhendone()
 
-    let listen = format!("{}:{}", ip, port);
-    let failthreshold: u32 = ft.parse().unwrap();
+    let listen = format!("{}:{}", settings.ip, settings.port);
+
+    if !["none", "secret", "hmac", "redis"].contains(&gate.mode.as_str()) {
+        return Err(HelperError::UnsupportedMode(format!("unknown handshake token check '{}'", gate.mode)))
+    }
+    if settings.schema != 1 && settings.schema != SCHEMAVERSION {
+        return Err(HelperError::UnsupportedMode(format!("unknown progress message schema {}", settings.schema)))
+    }
+    if settings.schema != 1 && settings.pools.format() == HelperFormat::Go {
+        return Err(HelperError::Config("the Go helper only sends schema 1 progress messages: use --wsv 1 with --compat go".to_string()))
+    }
 
     // https://github.com/snapview/tungstenite-rs/blob/master/examples/server.rs
-    env_logger::init();
-    let server = TcpListener::bind(listen).unwrap();
+    let server = TcpListener::bind(&listen)
+        .map_err(|e| HelperError::Config(format!("cannot listen on {}: {}", listen, e)))?;
+
+    // wss:// if we were handed a certificate; SIGHUP will reload it
+    let acceptor: Option<Arc<RwLock<TlsAcceptor>>> = match &settings.tls {
+        Some((cert, key)) => {
+            let a = ws_loadtls(cert, key).map_err(|e| HelperError::Config(format!("could not set up TLS: {}", e)))?;
+            let a = Arc::new(RwLock::new(a));
+            ws_reloadonhup(cert.clone(), key.clone(), a.clone())
+                .map_err(|e| HelperError::Config(format!("could not set up TLS: cannot listen for SIGHUP: {}", e)))?;
+            info!("WebSocket server will require TLS (wss://)");
+            Some(a)
+        }
+        None => None,
+    };
+
+    let settings = Arc::new(settings);
+    let registry: WsRegistry = Arc::new(Mutex::new(HashMap::new()));
+
     for stream in server.incoming() {
-        let r = rc.clone();
+        let s = settings.clone();
+        let g = gate.clone();
+        let r = registry.clone();
+        let t = acceptor.as_ref().map(|a| a.read().unwrap().clone());
         spawn(move || {
-            let callback = |_req: &Request, r: Response| {
-                Ok(r)
+            // [a] it launches and starts listening on a port
+            if let Ok(stream) = stream {
+                ws_serveconnection(stream, t, g, &s, &r);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn ws_serveconnection(stream: TcpStream, tls: Option<TlsAcceptor>, gate: WsGatekeeper, settings: &WsSettings, registry: &WsRegistry) {
+    // one client: either a websocket that will ask about search IDs or a one-shot HTTP GET
+
+    // nobody gets to hold a thread open by never finishing their request
+    let _ = stream.set_read_timeout(Some(HEADTIMEOUT));
+    let mut stream: WsStream = match tls {
+        Some(a) => match a.accept(stream) {
+            Ok(s) => Stream::Tls(s),
+            Err(e) => {
+                debug!("WebSocket TLS handshake failed: {}", e);
+                return
+            }
+        },
+        None => Stream::Plain(stream),
+    };
+
+    let head: Vec<u8> = match ws_readhead(&mut stream) {
+        Some(h) => h,
+        None => return,
+    };
+    let request: Request = match ws_parsehead(&head) {
+        Some(r) => r,
+        None => return,
+    };
+
+    let upgrading = request.headers().get("Upgrade")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+
+    if !upgrading {
+        ws_http(&mut stream, &request, &gate, settings, registry);
+        return
+    }
+
+    let _ = match &stream {
+        Stream::Plain(s) => s.set_read_timeout(None),
+        Stream::Tls(s) => s.get_ref().set_read_timeout(None),
+    };
+
+    let replay = WsReplay { head: Cursor::new(head), inner: stream };
+    let mut ws: WebSocket<WsReplay> = match accept_hdr(replay, gate) {
+        Ok(w) => w,
+        Err(_) => return,
+    };
+    mt_count(MtCounter::WsConnections);
+    mt_wsopen(1);
+
+    loop {
+        // [b] it waits to receive a websocket message: this is a search key ID (e.g., '2f81c630')
+        let msg: Message = match ws.read_message() {
+            Ok(m) => m,
+            // the client hung up
+            Err(_) => break,
+        };
+        if msg.is_close() {
+            break
+        }
+        if msg.is_text() && msg != Message::from("") {
+            let rk = String::from(msg.to_text().unwrap());
+
+            // at this point you have "ebf24e19" and NOT ebf24e19; fix that
+            let rediskey = rk.trim_matches('"');
+
+            if ws_runpollmessageloop(&mut ws, rediskey, settings, registry).is_err() {
+                break
+            }
+        }
+    }
+    mt_wsopen(-1);
+}
+
+fn ws_runpollmessageloop(ws: &mut WebSocket<WsReplay>, rediskey: &str, settings: &WsSettings, registry: &WsRegistry) -> Result<(), ()> {
+    // [c] and [d] for one search ID; an Err means that the client is gone
+    lg_setkey(rediskey);
+    let schema = settings.schema;
+    let watcher = WsWatcher::new(registry, rediskey);
+
+    let f = ws_fields();
+    let mut results: HashMap<String, String> = HashMap::new();
+    let mut previous: Option<JsonValue> = None;
+    let mut streamed: HashSet<String> = HashSet::new();
+    let mut sent: usize = 0;
+    let mut missing: u32 = 0u32;
+    let mut iterations: u32 = 0u32;
+
+    // this is the polling loop
+    loop {
+        thread::sleep(POLLINGINTERVAL);
+        iterations += 1;
+        watcher.tick(iterations);
+        trace!("WebSocket server reports that runpollmessageloop() for {} is on iteration {}", &rediskey, &iterations);
+
+        // borrow a connection for this tick only: an idle browser tab should not pin one
+        let mut redisconn = RsConn::new(&settings.pools);
+
+        // [c] it then looks inside of redis for the relevant polling data associated with that search ID
+        //  if redis cannot be reached this tick counts as a missing poll
+        if let Err(e) = ws_readpoll(rediskey, &f, &mut results, &mut redisconn) {
+            warn!("{}", e);
+            missing += 1;
+            if missing >= settings.failthreshold {
+                break
+            }
+            continue
+        }
+
+        // watch activity
+        // for (key, value) in &results {
+        //     trace!("{}: {}", key, value);
+        // }
+
+        // [d0] push any new hits before we consider quitting so that the last few are not lost
+        let hits = ws_newhits(rediskey, &mut streamed, &mut sent, settings.hitstostream, schema, &mut redisconn)
+            .unwrap_or_else(|e| {
+                warn!("{}", e);
+                Vec::new()
+            });
+        for h in hits {
+            ws.write_message(Message::text(h.dump())).map_err(|_| ())?;
+        }
+
+        let a = results.get("Active").unwrap();
+        if a != "yes" {
+            missing += 1;
+        }
+
+        // break if inactive
+        if missing >= settings.failthreshold {
+            info!("WebSocket broadcasting for {} halting after {} iterations: missing >= failthreshold", &rediskey, &iterations);
+            break
+        }
+
+        // [d2] package (as JSON)
+        let js: Option<JsonValue> = if schema >= SCHEMAVERSION {
+            let current = ws_typedresults(rediskey, &results);
+            let js = match &previous {
+                None => Some(current.clone()),
+                Some(p) => ws_deltaresults(p, &current),
             };
+            // compared against what the client last heard, not against the last tick
+            if js.is_some() {
+                previous = Some(current);
+            }
+            js
+        } else {
+            Some(ws_jsonifyresults(rediskey, results.clone()))
+        };
 
-            // [a] it launches and starts listening on a port
-            let mut ws: WebSocket<TcpStream> = accept_hdr(stream.unwrap(), callback).unwrap();
-
-            loop {
-                // [b] it waits to receive a websocket message: this is a search key ID (e.g., '2f81c630')
-                let msg: Message = ws.read_message().unwrap_or(Message::from("".to_string()));
-                if msg.is_text() && msg != Message::from("".to_string()) {
-                    let mut redisconn = redisconnect(r.to_string());
-                    let rk = String::from(msg.to_text().unwrap());
-
-                    // at this point you have "ebf24e19" and NOT ebf24e19; fix that
-                    let rk2 = String::from(rk.strip_prefix("\"").unwrap());
-                    let rediskey = rk2.strip_suffix("\"").unwrap();
-
-                    let f = ws_fields();
-                    let mut results: HashMap<String, String> = HashMap::new();
-                    let mut missing: u32 = 0u32;
-                    let mut iterations: u32 = 0u32;
-
-                    // this is the polling loop
-                    loop {
-                        thread::sleep(POLLINGINTERVAL);
-                        iterations += 1;
-                        let m: String = format!("WebSocket server reports that runpollmessageloop() for {} is on iteration {}", &rediskey, &iterations);
-                        lfl(m, ll, 3);
-
-                        // [c] it then looks inside of redis for the relevant polling data associated with that search ID
-                        for i in &f {
-                            let thekey: String = format!("{}_{}", rediskey, i);
-
-                            let mut capkey = i.to_owned().to_string();
-                            make_ascii_title_case(&mut capkey);
-
-                            let v = rs_get(&thekey, &mut redisconn);
-
-                            // [d] it parses, packages (as JSON), and then redistributes this information back over the websocket
-                            // [d1] insert as {"Launchtime": "1622578053.906691"}
-                            results.insert(capkey, v);
-                        }
-
-                        // watch activity
-                        // for (key, value) in &results {
-                        //     let m = format!("{}: {}", key, value);
-                        //     lfl(m, 0, 0);
-                        // }
-
-                        let a = results.get(&"Active".to_string()).unwrap();
-                        if a != "yes" {
-                            missing += 1;
-                        }
-
-                        // break if inactive
-                        if missing >= failthreshold {
-                            let m: String = format!("WebSocket broadcasting for {} halting after {} iterations: missing >= failthreshold", &rediskey, &iterations);
-                            lfl(m, ll, 1);
-                            break
-                        }
-
-                        // [d2] package (as JSON)
-                        let js = ws_jsonifyresults(&rediskey, results.clone());
-                        // [d3] redistribute this information
-                        ws.write_message(Message::text(js.dump())).unwrap();
-                    }
-
-                    //	[e] when the poll disappears from redis, the messages stop broadcasting
-                    // deletewhendone()
-                    if save != 0 {
-                        let thekey: String = format!("{}_poolofwork", rediskey);
-                        let _ = rs_set_int(thekey.as_str(), -1, &mut redisconn);
-                        let fields =  ws_fields();
-                        for f in fields {
-                            let _ = rs_del(f, &mut redisconn);
-                        }
-                        let m = format!("deleted redis keys for {}", &rediskey);
-                        lfl(m, ll, 4);
-                    }
+        // [d3] redistribute this information
+        if let Some(js) = js {
+            ws.write_message(Message::text(ws_dumpresults(&js, settings.pools.format()))).map_err(|_| ())?;
+        }
+    }
+
+    //	[e] when the poll disappears from redis, the messages stop broadcasting
+    // deletewhendone()
+    if settings.save != 0 {
+        let mut redisconn = RsConn::new(&settings.pools);
+        let thekey: String = format!("{}_poolofwork", rediskey);
+        let _ = rs_set_int(thekey.as_str(), -1, &mut redisconn);
+        let fields =  ws_fields();
+        for f in fields {
+            let _ = rs_del(f, &mut redisconn);
+        }
+        trace!("deleted redis keys for {}", &rediskey);
+    }
+    Ok(())
+}
+
+fn ws_readhead(stream: &mut WsStream) -> Option<Vec<u8>> {
+    // everything up to and including the blank line that ends the request head
+    let mut head: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAXHEADSIZE {
+            return None
+        }
+        let n = stream.read(&mut buf).ok()?;
+        if n == 0 {
+            return None
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Some(head)
+}
+
+fn ws_parsehead(head: &[u8]) -> Option<Request> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut parsed = httparse::Request::new(&mut headers);
+    parsed.parse(head).ok()?;
+    let mut builder = Request::builder()
+        .method(parsed.method?)
+        .uri(parsed.path?)
+        .version(Version::HTTP_11);
+    for h in parsed.headers.iter() {
+        builder = builder.header(h.name, h.value);
+    }
+    builder.body(()).ok()
+}
+
+fn ws_http(stream: &mut WsStream, request: &Request, gate: &WsGatekeeper, settings: &WsSettings, registry: &WsRegistry) {
+    // GET /healthz - can we reach redis and postgres?
+    // GET /status  - which search IDs are being watched, for how long, and by how many clients
+    //  (/status is subject to the same token check as a websocket handshake, but it does not use up a redis token)
+    let (status, body): (StatusCode, JsonValue) = if request.method() != Method::GET {
+        (StatusCode::METHOD_NOT_ALLOWED, JsonValue::from("GET only"))
+    } else {
+        match request.uri().path() {
+            "/healthz" => ws_healthz(settings),
+            "/status" => match ws_checktoken(gate, request, false) {
+                Ok(()) => (StatusCode::OK, ws_status(registry)),
+                Err(why) => (StatusCode::FORBIDDEN, JsonValue::from(why)),
+            },
+            _ => (StatusCode::NOT_FOUND, JsonValue::from("try /healthz or /status")),
+        }
+    };
+
+    let body = body.dump();
+    let reply = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status.as_u16(), status.canonical_reason().unwrap_or(""), body.len(), body);
+    let _ = stream.write_all(reply.as_bytes());
+    let _ = stream.flush();
+}
+
+fn ws_healthz(settings: &WsSettings) -> (StatusCode, JsonValue) {
+    // {"Redis": "ok", "PostgreSQL": "ok"}; 503 and the error message if either is not ok
+    let mut data = JsonValue::new_object();
+
+    let r = rs_ping(&mut RsConn::new(&settings.pools));
+    data["Redis"] = match &r {
+        Ok(_) => "ok".into(),
+        Err(e) => e.to_string().into(),
+    };
+
+    let p = settings.pools.pg()
+        .and_then(|mut c| c.simple_query("SELECT 1").map_err(HelperError::from));
+    data["PostgreSQL"] = match &p {
+        Ok(_) => "ok".into(),
+        Err(e) => e.message().into(),
+    };
+
+    if r.is_ok() && p.is_ok() {
+        (StatusCode::OK, data)
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, data)
+    }
+}
+
+fn ws_status(registry: &WsRegistry) -> JsonValue {
+    // {"Subscriptions": [{"ID": "ebf24e19", "Iterations": 12, "Clients": 1}, ...]}
+    let r = registry.lock().unwrap();
+    let mut ids: Vec<&String> = r.keys().collect();
+    ids.sort();
+
+    let mut subs = JsonValue::new_array();
+    for id in ids {
+        let mut s = JsonValue::new_object();
+        s["ID"] = id.as_str().into();
+        s["Iterations"] = r[id].iterations.into();
+        s["Clients"] = r[id].clients.into();
+        let _ = subs.push(s);
+    }
+
+    let mut data = JsonValue::new_object();
+    data["Subscriptions"] = subs;
+    data
+}
+
+fn ws_loadtls(cert: &str, key: &str) -> Result<TlsAcceptor, String> {
+    // PEM certificate chain + PEM (PKCS #8) private key
+    let c = fs::read(cert).map_err(|e| format!("cannot read {}: {}", cert, e))?;
+    let k = fs::read(key).map_err(|e| format!("cannot read {}: {}", key, e))?;
+    let id = Identity::from_pkcs8(&c, &k).map_err(|e| format!("bad certificate or key: {}", e))?;
+    TlsAcceptor::new(id).map_err(|e| e.to_string())
+}
+
+fn ws_reloadonhup(cert: String, key: String, acceptor: Arc<RwLock<TlsAcceptor>>) -> Result<(), String> {
+    // renewed certificates get picked up without dropping the open sockets
+    let mut signals = Signals::new([SIGHUP]).map_err(|e| e.to_string())?;
+    spawn(move || {
+        for _ in signals.forever() {
+            match ws_loadtls(&cert, &key) {
+                Ok(a) => {
+                    *acceptor.write().unwrap() = a;
+                    info!("SIGHUP: reloaded {}", &cert);
+                }
+                Err(e) => {
+                    warn!("SIGHUP: keeping the old certificate; reload failed: {}", e);
                 }
             }
-        });
+        }
+    });
+    Ok(())
+}
+
+fn ws_checkhandshake(gate: &WsGatekeeper, request: &Request) -> Result<(), String> {
+    // [a] the Origin header has to be on the list (if there is a list)
+    // [b] the token has to pass muster: see ws_checktoken()
+    ws_checkorigin(gate, request)?;
+    ws_checktoken(gate, request, true)
+}
+
+fn ws_checkorigin(gate: &WsGatekeeper, request: &Request) -> Result<(), String> {
+    if !gate.origins.is_empty() {
+        let origin = match request.headers().get("Origin") {
+            Some(o) => o.to_str().unwrap_or(""),
+            None => "",
+        };
+        if !gate.origins.iter().any(|o| o == origin) {
+            return Err(format!("origin '{}' is not allowed", origin));
+        }
     }
+    Ok(())
+}
+
+fn ws_checktoken(gate: &WsGatekeeper, request: &Request, spend: bool) -> Result<(), String> {
+    // the token has to pass muster for the selected mode:
+    //      "none"   - no token required
+    //      "secret" - ?token=<the shared secret>
+    //      "hmac"   - ?token=<expiry>.<hex HMAC-SHA256(secret, expiry)>; expiry is unix seconds
+    //      "redis"  - ?token=<t> where HipparchiaServer has already set "<t>_wstoken"; the key is deleted as it is
+    //                 checked, and only the client whose DEL removed it gets in, so that a token cannot be replayed
+    //                 (or used twice by two handshakes that arrive together); without "spend" (/status) the key is
+    //                 only looked at, so that a status check does not cost the socket that follows it
+    //  the token is percent-decoded first (a '+', '%' or '&' in the secret arrives encoded)
+
+    if gate.mode == "none" {
+        return Ok(())
+    }
+
+    let token: String = url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default();
+
+    if token.is_empty() {
+        return Err("no token supplied".to_string())
+    }
+
+    let valid: bool = match gate.mode.as_str() {
+        "secret" => !gate.secret.is_empty() && bool::from(token.as_bytes().ct_eq(gate.secret.as_bytes())),
+        "hmac" => ws_checkhmac(&token, &gate.secret),
+        "redis" => {
+            let thekey = format!("{}_wstoken", token);
+            let mut redisconn = RsConn::new(&gate.pools);
+            let found = match spend {
+                true => rs_del(&thekey, &mut redisconn).map(|deleted| deleted == 1),
+                false => rs_exists(&thekey, &mut redisconn),
+            };
+            match found {
+                Ok(found) => found,
+                Err(e) => {
+                    warn!("{}", e);
+                    false
+                }
+            }
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("token failed the '{}' check", gate.mode))
+    }
+}
+
+fn ws_checkhmac(token: &str, secret: &str) -> bool {
+    // "1622578053.9bd2c4..." is valid until 1622578053 if the signature matches
+    if secret.is_empty() {
+        return false
+    }
+
+    let (expiry, signature) = match token.split_once('.') {
+        Some(t) => t,
+        None => return false,
+    };
+
+    let expires: u64 = expiry.parse().unwrap_or(0);
+    let now: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(u64::MAX);
+    if expires < now {
+        return false
+    }
+
+    let signature: Vec<u8> = match hex::decode(signature) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    mac.update(expiry.as_bytes());
+    mac.verify_slice(&signature).is_ok()
 }
 
 fn ws_jsonifyresults(rediskey: &str, pd: HashMap<String, String>) -> json::JsonValue {
@@ -138,6 +628,147 @@ fn ws_jsonifyresults(rediskey: &str, pd: HashMap<String, String>) -> json::JsonV
     data
 }
 
+fn ws_dumpresults(js: &JsonValue, f: HelperFormat) -> String {
+    // the Go helper marshals a map: the keys come out in order
+    match f {
+        HelperFormat::Go => cp_gojson(&cp_sortedobject(js)),
+        HelperFormat::Rust => js.dump(),
+    }
+}
+
+fn ws_typedresults(rediskey: &str, pd: &HashMap<String, String>) -> JsonValue {
+    // schema 2: {"Schema": 2, "Type": "snapshot", "ID": "ebf24e19", "Active": true, "Hitcount": 12, "Percent": 37.5, ...}
+    // numbers that are not there yet (or are not numbers) come across as null
+    let int = |k: &str| -> JsonValue {
+        match pd.get(k).and_then(|v| v.trim().parse::<i64>().ok()) {
+            Some(n) => n.into(),
+            None => JsonValue::Null,
+        }
+    };
+    let text = |k: &str| -> JsonValue { pd.get(k).cloned().unwrap_or_default().into() };
+
+    let mut data = JsonValue::new_object();
+    data["Schema"] = SCHEMAVERSION.into();
+    data["Type"] = "snapshot".into();
+    data["ID"] = rediskey.into();
+    data["Active"] = (pd.get("Active").map(|a| a.as_str()) == Some("yes")).into();
+    data["Statusmessage"] = text("Statusmessage");
+    data["Notes"] = text("Notes");
+    data["Remaining"] = int("Remaining");
+    data["Poolofwork"] = int("Poolofwork");
+    data["Hitcount"] = int("Hitcount");
+    data["Portnumber"] = int("Portnumber");
+
+    // launchtime is python's time.time(): "1622578053.906691"
+    let launched: Option<f64> = pd.get("Launchtime").and_then(|v| v.trim().parse::<f64>().ok());
+    data["Launchtime"] = match launched {
+        Some(l) => l.into(),
+        None => JsonValue::Null,
+    };
+    let now: f64 = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    data["Elapsed"] = match launched {
+        Some(l) if now >= l => ((now - l) as i64).into(),
+        _ => JsonValue::Null,
+    };
+
+    // vector runs set poolofwork to -1 to turn off the progress figures
+    data["Percent"] = match (data["Poolofwork"].as_i64(), data["Remaining"].as_i64()) {
+        (Some(pool), Some(rem)) if pool > 0 && rem >= 0 => {
+            let done = (pool - rem.min(pool)) as f64 / pool as f64 * 100.0;
+            ((done * 10.0).round() / 10.0).into()
+        }
+        _ => JsonValue::Null,
+    };
+    data
+}
+
+fn ws_deltaresults(previous: &JsonValue, current: &JsonValue) -> Option<JsonValue> {
+    // only what changed since the last message; None if nothing did
+    //  "Elapsed" moves every second by itself: it rides along with a delta, but is never the reason for one
+    let mut data = JsonValue::new_object();
+    data["Schema"] = SCHEMAVERSION.into();
+    data["Type"] = "delta".into();
+    data["ID"] = current["ID"].clone();
+    let envelope = data.len();
+    for (key, val) in current.entries().filter(|(k, _)| *k != "Elapsed") {
+        if previous[key] != *val {
+            data[key] = val.clone();
+        }
+    }
+    if data.len() == envelope {
+        return None
+    }
+    if previous["Elapsed"] != current["Elapsed"] {
+        data["Elapsed"] = current["Elapsed"].clone();
+    }
+    Some(data)
+}
+
+pub fn ws_pollsnapshot(rediskey: &str, pools: &DbPools) -> HelperResult<JsonValue> {
+    // the progress of one search as a schema 2 snapshot, for callers that are not on the other end of a websocket
+    let mut results: HashMap<String, String> = HashMap::new();
+    ws_readpoll(rediskey, &ws_fields(), &mut results, &mut RsConn::new(pools))?;
+    Ok(ws_typedresults(rediskey, &results))
+}
+
+fn ws_readpoll(rediskey: &str, fields: &[&str], results: &mut HashMap<String, String>, c: &mut RsConn) -> RsResult<()> {
+    // {"Active": "yes", "Launchtime": "1622578053.906691", ...}; a key that is not there is ""
+    for i in fields {
+        let thekey: String = format!("{}_{}", rediskey, i);
+
+        let mut capkey = i.to_string();
+        make_ascii_title_case(&mut capkey);
+
+        let v = rs_get(&thekey, c)?.unwrap_or_default();
+
+        // [d] it parses, packages (as JSON), and then redistributes this information back over the websocket
+        // [d1] insert as {"Launchtime": "1622578053.906691"}
+        results.insert(capkey, v);
+    }
+    Ok(())
+}
+
+fn ws_newhits(rediskey: &str, streamed: &mut HashSet<String>, sent: &mut usize, cap: usize, schema: i32, c: &mut RsConn) -> RsResult<Vec<JsonValue>> {
+    // the hits in "<key>_results" that we have not sent yet; never more than cap in all
+    //  streamed is every member we have dealt with, sent only the ones that went out: a member that is not JSON is
+    //  remembered (so that it is parsed once) but does not use up one of the cap's places
+    if *sent >= cap {
+        return Ok(Vec::new())
+    }
+
+    let thekey = format!("{}_results", rediskey);
+    let mut fresh: Vec<String> = rs_smembers(&thekey, c)?.into_iter()
+        .filter(|h| !streamed.contains(h))
+        .collect();
+    fresh.sort();
+
+    let mut messages: Vec<JsonValue> = Vec::new();
+    for h in fresh {
+        if *sent >= cap {
+            break
+        }
+        let hit = json::parse(&h);
+        streamed.insert(h);
+        let hit = match hit {
+            Ok(j) => j,
+            Err(e) => {
+                warn!("{} holds a hit that is not JSON ({}): not streaming it", thekey, e);
+                continue
+            }
+        };
+        let mut data = JsonValue::new_object();
+        if schema >= SCHEMAVERSION {
+            data["Schema"] = SCHEMAVERSION.into();
+        }
+        data["Type"] = "hit".into();
+        data["ID"] = rediskey.into();
+        data["Hit"] = hit;
+        messages.push(data);
+        *sent += 1;
+    }
+    Ok(messages)
+}
+
 fn ws_fields<'a>() ->  Vec<&'a str> {
     // return the fields we are using
     // be careful about the key capitalization issue: go has "Active", etc.
@@ -0,0 +1,102 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// bench() over the fixture corpus: the shape of the report, not the timings themselves
+//  the reference searches are SQL, which the test corpus cannot run, so they are switched off with an empty list
+
+mod common;
+
+use std::sync::Arc;
+
+use common::*;
+use hipparchia_rust_dbhelper::*;
+
+fn settings(runs: usize, bagging: &str) -> BenchSettings {
+    BenchSettings {
+        runs,
+        table: "lt0448".to_string(),
+        start: 1,
+        end: 3,
+        bagging: bagging.to_string(),
+        bagsize: 1,
+        stops: (None, None),
+        searches: Some(Vec::new()),
+        cap: 200,
+        workers: 2,
+    }
+}
+
+fn seconds(j: &json::JsonValue) -> (f64, f64, f64) {
+    (j["Min"].as_f64().unwrap(), j["Median"].as_f64().unwrap(), j["Max"].as_f64().unwrap())
+}
+
+#[test]
+fn every_stage_every_run() {
+    let pools = pools();
+    let report = bench(&settings(3, "flat"), &pools).unwrap();
+    assert_eq!(report["Runs"], 3);
+    assert_eq!(report["Table"], "lt0448");
+    assert_eq!(report["Vectors"]["Bagging"], "flat");
+
+    let stages = &report["Vectors"]["Stages"];
+    let labels: Vec<String> = stages.members().map(|s| s["Stage"].to_string()).collect();
+    assert_eq!(labels, vec!["A", "B", "C", "D", "E", "F", "G", "H", "I", "J"]);
+    assert_eq!(stages[0]["Said"], "3 dblines fetched");
+    assert_eq!(stages[9]["Said"], "Stored 3 bags");
+
+    let mut last = 0.0;
+    for s in stages.members() {
+        let (min, median, max) = seconds(&s["At"]);
+        assert!(min <= median && median <= max);
+        let (spent, _, _) = seconds(&s["Spent"]);
+        assert!(spent <= min);
+        // the checkpoints only go up
+        assert!(median >= last);
+        last = median;
+    }
+    assert_eq!(report["Vectors"]["Total"], stages[9]["At"]);
+    assert!(report["Searches"].is_empty());
+
+    // nothing is left behind
+    let key = report["Key"].to_string();
+    assert!(key.starts_with("hrhbench_"));
+    for r in 0..3 {
+        assert!(!rs_exists(&format!("{}_v{}_vectorresults", key, r), &mut RsConn::new(&pools)).unwrap());
+    }
+}
+
+#[test]
+fn a_single_run() {
+    let pools = pools();
+    let report = bench(&settings(1, "winnertakesall"), &pools).unwrap();
+    let (min, median, max) = seconds(&report["Vectors"]["Total"]);
+    assert!(min == median && median == max);
+
+    // a second bench on the same store keeps to keys of its own
+    let again = bench(&settings(1, "winnertakesall"), &pools).unwrap();
+    assert_ne!(report["Key"], again["Key"]);
+}
+
+#[test]
+fn no_searches_over_files() {
+    // the plain --svc files cannot search: the built-in searches are left out, and asking for some is turned away up front
+    let pools = pools().withcorpus(Arc::new(FileCorpus::new(FIXTURES).unwrap()));
+    let mut s = settings(1, "flat");
+    s.searches = None;
+    let report = bench(&s, &pools).unwrap();
+    assert!(report["Searches"].is_empty());
+    assert_eq!(report["Vectors"]["Stages"].len(), 10);
+
+    s.searches = Some(vec!["amicitia".to_string()]);
+    let e = bench(&s, &pools).unwrap_err();
+    assert_eq!((e.kind(), e.exitcode()), ("unsupported", 6));
+}
+
+#[test]
+fn a_bad_bagging_method() {
+    // turned away before anything runs
+    let e = bench(&settings(1, "nosuchthing"), &pools()).unwrap_err();
+    assert_eq!((e.kind(), e.exitcode()), ("unsupported", 6));
+}
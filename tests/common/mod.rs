@@ -0,0 +1,119 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// what every test needs: pools with no servers behind them
+//  redis is a MemoryStore; postgres is the fixture corpus in tests/fixtures, read by a FileCorpus
+//  that can also run "work items" of the form {"PsqlQuery": "<table>", "PsqlData": "<text in stripped_line>"}
+
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use hipparchia_rust_dbhelper::*;
+
+pub static FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+pub struct TestCorpus {
+    files: FileCorpus,
+    // how many times a connection has been finish()ed
+    finished: Arc<AtomicUsize>,
+}
+
+impl CorpusSource for TestCorpus {
+    fn open(&self) -> HelperResult<Box<dyn CorpusConn>> {
+        Ok(Box::new(TestCorpusConn { files: self.files.open()?, finished: self.finished.clone() }))
+    }
+
+    fn describe(&self) -> String {
+        format!("the test corpus ({})", self.files.describe())
+    }
+
+    fn searches(&self) -> bool {
+        // its own kind of work item only: see workitem() below
+        true
+    }
+}
+
+struct TestCorpusConn {
+    files: Box<dyn CorpusConn>,
+    finished: Arc<AtomicUsize>,
+}
+
+impl CorpusConn for TestCorpusConn {
+    fn lines(&mut self, table: &str, first: i32, last: i32) -> HelperResult<Vec<DBLine>> {
+        self.files.lines(table, first, last)
+    }
+
+    fn workitem(&mut self, _temptable: &str, query: &str, data: &str) -> HelperResult<Vec<DBLine>> {
+        // the stand-in for SQL: every line of the table whose stripped_line contains data
+        let lines = self.files.lines(query, i32::MIN, i32::MAX)?;
+        Ok(lines.into_iter().filter(|l| l.st.contains(data)).collect())
+    }
+
+    fn morphology(&mut self, words: &[String], lang: &str) -> HelperResult<Vec<DbMorphology>> {
+        self.files.morphology(words, lang)
+    }
+
+    fn headwordcounts(&mut self, headwords: &[String]) -> HelperResult<Vec<WeightedHeadword>> {
+        self.files.headwordcounts(headwords)
+    }
+
+    fn schema(&mut self, linetable: Option<&str>) -> HelperResult<DbSchema> {
+        self.files.schema(linetable)
+    }
+
+    fn finish(&mut self) -> HelperResult<()> {
+        self.finished.fetch_add(1, Ordering::SeqCst);
+        self.files.finish()
+    }
+}
+
+pub fn pools() -> DbPools {
+    counted().0
+}
+
+pub fn counted() -> (DbPools, Arc<AtomicUsize>) {
+    // a fresh store every time, so that the tests cannot see each other's keys
+    //  the postgres login is never used: the pool only connects on checkout
+    //  and a count of the corpus connections that were finish()ed, i.e. cleaned up for the next user
+    let store = Arc::new(MemoryStore::new());
+    let finished = Arc::new(AtomicUsize::new(0));
+    let corpus = Arc::new(TestCorpus { files: FileCorpus::new(FIXTURES).unwrap(), finished: finished.clone() });
+    (DbPools::withstore(PGDEFAULT, store, poolsize(2)).unwrap().withcorpus(corpus), finished)
+}
+
+pub fn workitem(table: &str, text: &str) -> String {
+    let mut j = json::JsonValue::new_object();
+    j["TempTable"] = "".into();
+    j["PsqlQuery"] = table.into();
+    j["PsqlData"] = text.into();
+    j.dump()
+}
+
+pub fn members(key: &str, pools: &DbPools) -> Vec<String> {
+    // SMEMBERS, sorted
+    let mut m = rs_smembers(key, &mut RsConn::new(pools)).unwrap();
+    m.sort();
+    m
+}
+
+pub fn get(key: &str, pools: &DbPools) -> Option<String> {
+    rs_get(key, &mut RsConn::new(pools)).unwrap()
+}
+
+pub fn set(key: &str, value: &str, pools: &DbPools) {
+    rs_set_str(key, value, &mut RsConn::new(pools)).unwrap()
+}
+
+pub fn bags(key: &str, pools: &DbPools) -> Vec<(String, String)> {
+    // "<key>_vectorresults" as sorted (locus, bag) pairs
+    let mut b: Vec<(String, String)> = members(&format!("{}_vectorresults", key), pools).iter()
+        .map(|m| json::parse(m).unwrap())
+        .map(|j| (j["Loc"].to_string(), j["Bag"].to_string()))
+        .collect();
+    b.sort();
+    b
+}
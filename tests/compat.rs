@@ -0,0 +1,132 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// --compat go and cp_compare(): what HipparchiaGoDBHelper would have written, and how two result sets differ
+
+mod common;
+
+use common::*;
+use hipparchia_rust_dbhelper::*;
+
+fn gopools() -> DbPools {
+    pools().withformat(HelperFormat::Go)
+}
+
+#[test]
+fn go_escaping() {
+    let mut j = json::JsonValue::new_object();
+    j["WkUID"] = "lt0448w001".into();
+    j["TbIndex"] = 7.into();
+    j["MarkedUp"] = "<hmu_roman_in_a_greek_text>Caesar & co</hmu_roman_in_a_greek_text>".into();
+    j["Notes"] = "a\tb\nc\u{8}d\u{2028}\"e\\".into();
+    assert_eq!(cp_gojson(&j), concat!(r#"{"WkUID":"lt0448w001","TbIndex":7,"#,
+        r#""MarkedUp":"\u003chmu_roman_in_a_greek_text\u003eCaesar \u0026 co\u003c/hmu_roman_in_a_greek_text\u003e","#,
+        r#""Notes":"a\tb\nc\u0008d\u2028\"e\\"}"#));
+    assert_eq!(json::parse(&cp_gojson(&j)).unwrap(), j);
+    assert_eq!(cp_dump(&j, HelperFormat::Rust), j.dump());
+}
+
+#[test]
+fn go_polls_are_sorted() {
+    let mut j = json::JsonValue::new_object();
+    j["Remaining"] = "3".into();
+    j["Active"] = "yes".into();
+    j["ID"] = "ebf24e19".into();
+    assert_eq!(cp_gojson(&cp_sortedobject(&j)), r#"{"Active":"yes","ID":"ebf24e19","Remaining":"3"}"#);
+}
+
+#[test]
+fn go_hits_and_bags() {
+    // nothing in the fixture needs escaping, so the two helpers agree byte for byte
+    let (rust, go) = (pools(), gopools());
+    for p in [&rust, &go] {
+        rs_sadd("c1", &workitem("lt0448", "belgae"), &mut RsConn::new(p)).unwrap();
+        grabber(200, "c1".to_string(), 1, p).unwrap();
+        vector_prep("rusttest", "flat", 1, 1, "lt0448", 1, 3, (None, None), p).unwrap();
+    }
+    for key in ["c1_results", "rusttest_vectorresults"] {
+        let (a, b) = (members(key, &rust), members(key, &go));
+        assert_eq!(a.len(), if key == "c1_results" { 2 } else { 3 });
+        assert_eq!(a, b);
+    }
+}
+
+#[test]
+fn comparing_bags() {
+    let pools = pools();
+    vector_prep("rusttest", "flat", 1, 1, "lt0448", 1, 3, (None, None), &pools).unwrap();
+    let flat = members("rusttest_vectorresults", &pools);
+    // the first two lines only, and from the other helper
+    let go = gopools();
+    vector_prep("rusttest", "alternates", 1, 1, "lt0448", 1, 2, (None, None), &go).unwrap();
+    let alternates = members("rusttest_vectorresults", &go);
+    assert_eq!(alternates.len(), 2);
+
+    let s = cp_compare(("flat", &flat), ("alternates", &alternates), 10).unwrap();
+    assert_eq!(s["Kind"], "bags");
+    assert_eq!(s["A"]["Items"], 3);
+    assert_eq!(s["B"]["Items"], 2);
+    assert_eq!(s["Keys"]["Both"], 2);
+    assert_eq!(s["Keys"]["OnlyA"], 1);
+    assert_eq!(s["Keys"]["OnlyB"], 0);
+    assert_eq!(s["Examples"]["OnlyA"][0], "line/lt0448w001/3");
+    assert_eq!(s["Content"]["Identical"], 1);
+    assert_eq!(s["Content"]["Different"], 1);
+    assert_eq!(s["Examples"]["Different"][0]["Key"], "line/lt0448w001/1");
+    assert_eq!(s["Examples"]["Different"][0]["OnlyA"].len(), 0);
+    assert_eq!(s["Examples"]["Different"][0]["OnlyB"][0], "sum¹·edo¹");
+    assert_eq!(s["Same"], false);
+
+    // line 3 is only in A, so its words count against B: 7 of them, then sum¹·edo¹
+    assert_eq!(s["Words"].len(), 8);
+    assert_eq!(s["Words"][0]["Word"], "Belgae");
+    assert_eq!((s["Words"][0]["A"].as_usize(), s["Words"][0]["B"].as_usize()), (Some(2), Some(1)));
+    assert_eq!(s["Words"][7]["Word"], "sum¹·edo¹");
+
+    let same = cp_compare(("flat", &flat), ("flat again", &flat), 10).unwrap();
+    assert_eq!(same["Content"]["Identical"], 3);
+    assert_eq!(same["Same"], true);
+}
+
+#[test]
+fn comparing_hits() {
+    // the same hit from each helper, and one that disagrees about a field
+    let mut hit = json::JsonValue::new_object();
+    hit["WkUID"] = "lt0448w001".into();
+    hit["TbIndex"] = 1.into();
+    hit["MarkedUp"] = "<span>Gallia</span>".into();
+    let mut other = hit.clone();
+    other["TbIndex"] = 2.into();
+    let mut changed = other.clone();
+    changed["MarkedUp"] = "Gallia".into();
+
+    let a = vec![hit.dump(), other.dump()];
+    let b = vec![cp_gojson(&hit), cp_gojson(&changed)];
+    let s = cp_compare(("rust", &a), ("go", &b), 10).unwrap();
+    assert_eq!(s["Kind"], "hits");
+    assert_eq!(s["Keys"]["Both"], 2);
+    assert_eq!(s["Content"]["Identical"], 0);
+    assert_eq!(s["Content"]["Equivalent"], 1);
+    assert_eq!(s["Content"]["Different"], 1);
+    assert!(s["Content"]["Reordered"].is_null());
+    assert_eq!(s["Fields"]["MarkedUp"], 1);
+    assert_eq!(s["Examples"]["Different"][0]["Key"], "lt0448w001/2");
+    assert_eq!(s["Same"], false);
+
+    let bags = vec![r#"{"Loc":"line/lt0448w001/1","Bag":"gallia"}"#.to_string()];
+    let e = cp_compare(("rust", &a), ("bags", &bags), 10).unwrap_err();
+    assert_eq!((e.kind(), e.exitcode()), ("config", 2));
+}
+
+#[test]
+fn moved_bags() {
+    // the same words under another locus: e.g., where one helper cut a sentence somewhere else
+    let a = vec![r#"{"Loc":"line/lt0448w001/1","Bag":"gallia divido"}"#.to_string(), r#"{"Loc":"line/lt0448w001/2","Bag":"belgae"}"#.to_string()];
+    let b = vec![r#"{"Loc":"line/lt0448w001/1","Bag":"divido gallia"}"#.to_string(), r#"{"Loc":"line/lt0448w001/3","Bag":"belgae"}"#.to_string()];
+    let s = cp_compare(("a", &a), ("b", &b), 10).unwrap();
+    assert_eq!(s["Content"]["Reordered"], 1);
+    assert_eq!(s["Keys"]["Moved"], 1);
+    assert_eq!(s["Words"].len(), 0);
+}
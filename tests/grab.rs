@@ -0,0 +1,96 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// grabber() against the fixture corpus: the hits are checked as the JSON that HipparchiaServer reads
+
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::*;
+use hipparchia_rust_dbhelper::*;
+
+static BELGAE1: &str = r#"{"WkUID":"lt0448w001","TbIndex":1,"Lvl5Value":"-1","Lvl4Value":"-1","Lvl3Value":"-1","Lvl2Value":"1","Lvl1Value":"1","Lvl0Value":"1","MarkedUp":"Gallia est omnis divisa in partes tres, quarum unam incolunt Belgae.","Accented":"gallia est omnis divisa in partes tres quarum unam incolunt belgae","Stripped":"gallia est omnis diuisa in partes tres quarum unam incolunt belgae","Hypenated":"","Annotations":""}"#;
+static BELGAE3: &str = r#"{"WkUID":"lt0448w001","TbIndex":3,"Lvl5Value":"-1","Lvl4Value":"-1","Lvl3Value":"-1","Lvl2Value":"1","Lvl1Value":"1","Lvl0Value":"3","MarkedUp":"Belgae proximi sunt Germanis, Caesar bellum gerit.","Accented":"belgae proximi sunt germanis caesar bellum gerit","Stripped":"belgae proximi sunt germanis caesar bellum gerit","Hypenated":"","Annotations":""}"#;
+static MENIN: &str = r#"{"WkUID":"gr0012w001","TbIndex":1,"Lvl5Value":"-1","Lvl4Value":"-1","Lvl3Value":"-1","Lvl2Value":"-1","Lvl1Value":"1","Lvl0Value":"1","MarkedUp":"μῆνιν ἄειδε θεὰ Πηληϊάδεω Ἀχιλῆοϲ","Accented":"μῆνιν ἄειδε θεὰ πηληϊάδεω ἀχιλῆοϲ","Stripped":"μηνιν αειδε θεα πηληιαδεω αχιληοϲ","Hypenated":"","Annotations":""}"#;
+
+fn search(key: &str, items: &[String], cap: i32, workers: i32) -> DbPools {
+    let pools = pools();
+    let mut c = RsConn::new(&pools);
+    for i in items {
+        rs_sadd(key, i, &mut c).unwrap();
+    }
+    let resultkey = grabber(cap, key.to_string(), workers, &pools).unwrap();
+    assert_eq!(resultkey, format!("{}_results", key));
+    pools
+}
+
+#[test]
+fn hits() {
+    let pools = search("g1", &[workitem("lt0448", "belgae")], 200, 1);
+    assert_eq!(members("g1_results", &pools), vec![BELGAE1, BELGAE3]);
+}
+
+#[test]
+fn greek_hits() {
+    // a null in the JSONL is read as ""
+    let pools = search("g2", &[workitem("gr0012", "μηνιν")], 200, 1);
+    assert_eq!(members("g2_results", &pools), vec![MENIN]);
+}
+
+#[test]
+fn several_workers_and_items() {
+    let items = [workitem("lt0448", "belgae"), workitem("gr0012", "μηνιν"), workitem("lt0448", "nothing like this")];
+    let pools = search("g3", &items, 200, 3);
+    assert_eq!(members("g3_results", &pools), vec![MENIN, BELGAE1, BELGAE3]);
+}
+
+#[test]
+fn progress_poll() {
+    let pools = search("g4", &[workitem("lt0448", "belgae"), workitem("gr0012", "μηνιν")], 200, 1);
+    assert_eq!(get("g4_poolofwork", &pools).as_deref(), Some("2"));
+    assert_eq!(get("g4_remaining", &pools).as_deref(), Some("0"));
+    assert_eq!(get("g4_hitcount", &pools).as_deref(), Some("3"));
+
+    let snapshot = ws_pollsnapshot("g4", &pools).unwrap();
+    assert_eq!(snapshot["Hitcount"], 3);
+    assert_eq!(snapshot["Percent"], 100);
+    assert_eq!(snapshot["Active"], false);
+}
+
+#[test]
+fn the_cap() {
+    // the hit that would go over the cap is not stored, and the rest of the work pile is dropped
+    let pools = search("g5", &[workitem("lt0448", "belgae")], 1, 1);
+    assert_eq!(members("g5_results", &pools), vec![BELGAE1]);
+    assert_eq!(get("g5_hitcount", &pools).as_deref(), Some("1"));
+    assert!(!rs_exists("g5", &mut RsConn::new(&pools)).unwrap());
+}
+
+#[test]
+fn no_work() {
+    let pools = search("g6", &[], 200, 2);
+    assert!(members("g6_results", &pools).is_empty());
+    assert_eq!(get("g6_poolofwork", &pools).as_deref(), Some("0"));
+}
+
+#[test]
+fn a_bad_work_item() {
+    let (pools, finished) = counted();
+    rs_sadd("g7", "SELECT * FROM lt0448", &mut RsConn::new(&pools)).unwrap();
+    let e = grabber(200, "g7".to_string(), 1, &pools).unwrap_err();
+    assert_eq!((e.kind(), e.exitcode()), ("workitem", 5));
+    // the worker gave up halfway, but its connection was still cleaned up
+    assert_eq!(finished.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn a_corpus_that_cannot_search() {
+    // the --svc files have no SQL to run a work item with
+    let pools = pools().withcorpus(std::sync::Arc::new(FileCorpus::new(FIXTURES).unwrap()));
+    rs_sadd("g8", &workitem("lt0448", "belgae"), &mut RsConn::new(&pools)).unwrap();
+    let e = grabber(200, "g8".to_string(), 1, &pools).unwrap_err();
+    assert_eq!((e.kind(), e.exitcode()), ("unsupported", 6));
+}
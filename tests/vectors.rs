@@ -0,0 +1,150 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// vector_prep() and BagPipeline against the fixture corpus: every bag is checked word for word
+
+mod common;
+
+use common::*;
+use hipparchia_rust_dbhelper::*;
+
+fn direct(method: &str, table: &str, last: i32, stops: (Option<String>, Option<String>)) -> Vec<(String, String)> {
+    // "vectors --svdb <table> --svs 1 --sve <last>"
+    let pools = pools();
+    let resultkey = vector_prep("rusttest", method, 1, 1, table, 1, last, stops, &pools).unwrap();
+    assert_eq!(resultkey, "rusttest_vectorresults");
+    bags("rusttest", &pools)
+}
+
+fn nostops() -> (Option<String>, Option<String>) {
+    (Some(String::new()), Some(String::new()))
+}
+
+fn pairs(p: &[(&str, &str)]) -> Vec<(String, String)> {
+    p.iter().map(|(l, b)| (l.to_string(), b.to_string())).collect()
+}
+
+#[test]
+fn flat_latin() {
+    assert_eq!(direct("flat", "lt0448", 3, (None, None)), pairs(&[
+        ("line/lt0448w001/1", "Gallia divido tres quarum unam incolunt Belgae"),
+        ("line/lt0448w001/2", "hi lingua institutis lex differo"),
+        ("line/lt0448w001/3", "Belgae proximus Germanus germanus Caesar bellum gero"),
+    ]));
+}
+
+#[test]
+fn flat_latin_without_stopwords() {
+    assert_eq!(direct("flat", "lt0448", 3, nostops()), pairs(&[
+        ("line/lt0448w001/1", "Gallia sum¹ edo¹ omnis divido in pars tres quarum unam incolunt Belgae"),
+        ("line/lt0448w001/2", "hi omnes lingua institutis lex inter se differo"),
+        ("line/lt0448w001/3", "Belgae proximus sunt Germanus germanus Caesar bellum gero"),
+    ]));
+}
+
+#[test]
+fn flat_greek() {
+    // no full stop at the end of line 1, so both lines are one sentence (and one bag)
+    assert_eq!(direct("flat", "gr0012", 2, (None, None)), pairs(&[
+        ("line/gr0012w001/1", "μῆνιϲ ἀείδω θεά πηληάδεω Ἀχιλλεύϲ οὐλόμενοϲ ἣ μυρίοϲ Ἀχαιόϲ ἄλγοϲ τίθημι"),
+    ]));
+}
+
+#[test]
+fn alternates_latin() {
+    assert_eq!(direct("alternates", "lt0448", 3, (None, None)), pairs(&[
+        ("line/lt0448w001/1", "Gallia sum¹·edo¹ divido tres quarum unam incolunt Belgae"),
+        ("line/lt0448w001/2", "hi lingua institutis lex differo"),
+        ("line/lt0448w001/3", "Belgae proximus Germanus·germanus Caesar bellum gero"),
+    ]));
+}
+
+#[test]
+fn alternates_greek() {
+    assert_eq!(direct("alternates", "gr0012", 2, (None, None)), pairs(&[
+        ("line/gr0012w001/1", "μῆνιϲ ἀείδω θεά·θεόϲ πηληάδεω Ἀχιλλεύϲ οὐλόμενοϲ ἣ μυρίοϲ Ἀχαιόϲ ἄλγοϲ τίθημι"),
+    ]));
+}
+
+#[test]
+fn winnertakesall_latin() {
+    assert_eq!(direct("winnertakesall", "lt0448", 3, (None, None)), pairs(&[
+        ("line/lt0448w001/1", "gallia divido tres quarum unam incolunt belgae"),
+        ("line/lt0448w001/2", "hi lingua institutis lex differo"),
+        ("line/lt0448w001/3", "belgae proximus germanus caesar bellum gero"),
+    ]));
+}
+
+#[test]
+fn winnertakesall_greek() {
+    // θεά·θεόϲ goes to θεόϲ (50 to 20 in dictionary_headword_wordcounts)
+    assert_eq!(direct("winnertakesall", "gr0012", 2, nostops()), pairs(&[
+        ("line/gr0012w001/1", "μῆνιϲ ἀείδω θεόϲ πηληάδεω ἀχιλλεύϲ οὐλόμενοϲ ἣ μυρίοϲ ἀχαιόϲ ἄλγοϲ τίθημι"),
+    ]));
+}
+
+#[test]
+fn a_range_of_lines() {
+    assert_eq!(direct("flat", "lt0448", 1, (None, None)), pairs(&[
+        ("line/lt0448w001/1", "Gallia divido tres quarum unam incolunt Belgae"),
+    ]));
+}
+
+#[test]
+fn work_items() {
+    // the lines come from the work items under the key (--svdb is ignored), and the work pile is used up
+    //  the items come off in any order, so every line here ends its sentence
+    let pools = pools();
+    let mut c = RsConn::new(&pools);
+    rs_sadd("v1", &workitem("lt0448", "belgae"), &mut c).unwrap();
+    rs_sadd("v1", &workitem("lt0448", "lingua"), &mut c).unwrap();
+
+    let resultkey = vector_prep("v1", "flat", 1, 1, "gr0012", 1, 2, (None, None), &pools).unwrap();
+    assert_eq!(resultkey, "v1_vectorresults");
+    assert_eq!(bags("v1", &pools), pairs(&[
+        ("line/lt0448w001/1", "Gallia divido tres quarum unam incolunt Belgae"),
+        ("line/lt0448w001/2", "hi lingua institutis lex differo"),
+        ("line/lt0448w001/3", "Belgae proximus Germanus germanus Caesar bellum gero"),
+    ]));
+    assert_eq!(rs_scard("v1", &mut c).unwrap(), 0);
+    assert_eq!(get("v1_remaining", &pools).as_deref(), Some("0"));
+
+    // vector runs turn the progress figures off
+    assert_eq!(get("v1_poolofwork", &pools).as_deref(), Some("-1"));
+    assert_eq!(get("v1_hitcount", &pools).as_deref(), Some("0"));
+}
+
+#[test]
+fn a_pipeline_that_does_not_store() {
+    let pools = pools();
+    let mut p = BagPipeline::standard("flat", 1, "lt0448", 2, 2, (None, None)).unwrap();
+    p.remove("J").unwrap();
+    assert_eq!(p.labels(), vec!["A", "B", "C", "D", "E", "F", "G", "H", "I"]);
+
+    let st = p.execute("v2", &pools).unwrap();
+    assert_eq!(st.bags.get("line/lt0448w001/2").map(|b| b.as_str()), Some("hi lingua institutis lex differo"));
+    assert_eq!(st.bags.len(), 1);
+    assert_eq!(st.resultkey, "");
+    assert!(members("v2_vectorresults", &pools).is_empty());
+}
+
+#[test]
+fn unsupported_bagging() {
+    let pools = pools();
+    let e = vector_prep("rusttest", "unlemmatized", 1, 1, "lt0448", 1, 3, (None, None), &pools).unwrap_err();
+    assert_eq!((e.kind(), e.exitcode()), ("unsupported", 6));
+    assert!(BagPipeline::standard("flat", 1, "", 0, 0, (None, None)).unwrap().remove("Z").is_err());
+}
+
+#[test]
+fn fixture_schema() {
+    // the fixture passes the same checks as a HipparchiaBuilder 1.5.0b+ database
+    let pools = pools();
+    let schema = pools.corpus().open().unwrap().schema(None).unwrap();
+    assert_eq!(schema.generation, "1.5.0b+");
+    assert_eq!(schema.linetable.as_deref(), Some("gr0012"));
+    schema.requirelines().unwrap();
+    schema.requiremorphology().unwrap();
+}
@@ -0,0 +1,282 @@
+//    HipparchiaRustDBHelper: search and vector helper app and functions for HipparchiaServer
+//    Copyright: E Gunderson 2021
+//    License: GNU GENERAL PUBLIC LICENSE 3
+//        (see LICENSE in the top level directory of the distribution)
+
+// a websocket() on a spare port, watched by a tungstenite client: the poll keys are changed between ticks
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use json::JsonValue;
+use tungstenite::{client, Message, WebSocket};
+
+use common::*;
+use hipparchia_rust_dbhelper::*;
+
+fn gate(mode: &str, secret: &str, pools: &DbPools) -> WsGatekeeper {
+    WsGatekeeper { origins: Vec::new(), mode: mode.to_string(), secret: secret.to_string(), pools: pools.clone() }
+}
+
+fn serve(schema: i32, pools: &DbPools) -> u16 {
+    serve_gated(schema, gate("none", "", pools), pools)
+}
+
+fn serve_gated(schema: i32, gate: WsGatekeeper, pools: &DbPools) -> u16 {
+    // ask the OS for a free port, let go of it and hand it to websocket()
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let settings = WsSettings {
+        failthreshold: 1,
+        ip: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        save: 0,
+        schema,
+        hitstostream: 5,
+        pools: pools.clone(),
+        tls: None,
+    };
+    thread::spawn(move || websocket(settings, gate).unwrap());
+    port
+}
+
+fn connect(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(s) = TcpStream::connect(("127.0.0.1", port)) {
+            s.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+            return s
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("nothing is listening on {}", port)
+}
+
+fn subscribe(port: u16, key: &str) -> WebSocket<TcpStream> {
+    let (mut ws, _) = client(format!("ws://127.0.0.1:{}/", port), connect(port)).unwrap();
+    // HipparchiaServer sends the key with its quotes on
+    ws.write_message(Message::text(format!("\"{}\"", key))).unwrap();
+    ws
+}
+
+fn admitted(port: u16, query: &str) -> bool {
+    // does the handshake for ws://127.0.0.1:<port>/?<query> succeed?
+    client(format!("ws://127.0.0.1:{}/?{}", port, query), connect(port)).is_ok()
+}
+
+fn next(ws: &mut WebSocket<TcpStream>) -> JsonValue {
+    json::parse(ws.read_message().unwrap().to_text().unwrap()).unwrap()
+}
+
+fn poll(key: &str, fields: &[(&str, &str)], pools: &DbPools) {
+    for (f, v) in fields {
+        set(&format!("{}_{}", key, f), v, pools);
+    }
+}
+
+fn get_http(port: u16, path: &str) -> (String, JsonValue) {
+    let mut s = connect(port);
+    write!(s, "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", path).unwrap();
+    let mut reply = String::new();
+    s.read_to_string(&mut reply).unwrap();
+    let (head, body) = reply.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), json::parse(body).unwrap())
+}
+
+#[test]
+fn snapshots_hits_and_deltas() {
+    let pools = pools();
+    poll("w1", &[("active", "yes"), ("statusmessage", "Searching..."), ("remaining", "2"), ("poolofwork", "2"), ("hitcount", "0")], &pools);
+    let port = serve(2, &pools);
+    let mut ws = subscribe(port, "w1");
+
+    let snapshot = next(&mut ws);
+    assert_eq!(snapshot["Schema"], 2);
+    assert_eq!(snapshot["Type"], "snapshot");
+    assert_eq!(snapshot["ID"], "w1");
+    assert_eq!(snapshot["Active"], true);
+    assert_eq!(snapshot["Statusmessage"], "Searching...");
+    assert_eq!(snapshot["Remaining"], 2);
+    assert_eq!(snapshot["Percent"], 0);
+    assert!(snapshot["Launchtime"].is_null());
+
+    // one work item done and a hit found: the hit goes in first, so that the tick which sees the new counts has sent it
+    rs_sadd("w1_results", r#"{"WkUID":"lt0448w001","TbIndex":1}"#, &mut RsConn::new(&pools)).unwrap();
+    poll("w1", &[("remaining", "1"), ("hitcount", "1")], &pools);
+
+    let hit = next(&mut ws);
+    assert_eq!(hit["Type"], "hit");
+    assert_eq!(hit["ID"], "w1");
+    assert_eq!(hit["Hit"]["TbIndex"], 1);
+
+    // the two counts are set one after the other, so a tick may fall between them and send two deltas
+    let mut changed: Vec<String> = Vec::new();
+    let mut percent = JsonValue::Null;
+    while !(changed.iter().any(|k| k == "Remaining") && changed.iter().any(|k| k == "Hitcount")) {
+        let delta = next(&mut ws);
+        assert_eq!(delta["Type"], "delta");
+        for (k, v) in delta.entries().filter(|(k, _)| !["Schema", "Type", "ID"].contains(k)) {
+            if k == "Percent" {
+                percent = v.clone();
+            }
+            if !changed.iter().any(|c| c == k) {
+                changed.push(k.to_string());
+            }
+        }
+    }
+    changed.sort();
+    assert_eq!(changed, vec!["Hitcount", "Percent", "Remaining"]);
+    assert_eq!(percent, 50);
+
+    // while it is watched, /status knows about it
+    let (status, body) = get_http(port, "/status");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["Subscriptions"][0]["ID"], "w1");
+    assert_eq!(body["Subscriptions"][0]["Clients"], 1);
+
+    // the search ends: no more messages
+    poll("w1", &[("active", "no")], &pools);
+    assert!(ws.read_message().is_err());
+}
+
+#[test]
+fn the_clock_alone_sends_no_delta() {
+    // a search that started a minute ago: "Elapsed" goes up every second, but nothing else moves for a while
+    let pools = pools();
+    let launched = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64() - 60.0;
+    poll("w5", &[("active", "yes"), ("remaining", "2"), ("poolofwork", "2"), ("launchtime", &launched.to_string())], &pools);
+    let port = serve(2, &pools);
+    let mut ws = subscribe(port, "w5");
+    let snapshot = next(&mut ws);
+    assert_eq!(snapshot["Type"], "snapshot");
+    let started = snapshot["Elapsed"].as_i64().unwrap();
+
+    thread::sleep(Duration::from_millis(2200));
+    poll("w5", &[("remaining", "1")], &pools);
+
+    // the first delta is the one for Remaining, and it brings Elapsed up to date
+    let delta = next(&mut ws);
+    assert_eq!(delta["Type"], "delta");
+    assert_eq!(delta["Remaining"], 1);
+    assert!(delta["Elapsed"].as_i64().unwrap() >= started + 2);
+}
+
+#[test]
+fn hits_that_are_not_json() {
+    // skipped, and they do not count against --wsx (5 here): "garbage" sorts ahead of every '{'
+    let pools = pools();
+    poll("w4", &[("active", "yes"), ("hitcount", "5")], &pools);
+    let mut hits: Vec<String> = (1..=5).map(|i| format!(r#"{{"WkUID":"lt0448w001","TbIndex":{}}}"#, i)).collect();
+    hits.push("garbage".to_string());
+    rs_saddmany("w4_results", &hits, &mut RsConn::new(&pools)).unwrap();
+    let port = serve(2, &pools);
+    let mut ws = subscribe(port, "w4");
+
+    let mut indices: Vec<i32> = Vec::new();
+    for _ in 0..6 {
+        let m = next(&mut ws);
+        if m["Type"] == "hit" {
+            indices.push(m["Hit"]["TbIndex"].as_i32().unwrap());
+        }
+    }
+    assert_eq!(indices, vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn schema_one() {
+    // every field, as a string, on every tick
+    let pools = pools();
+    poll("w2", &[("active", "yes"), ("hitcount", "7"), ("launchtime", "1622578053.906691")], &pools);
+    let port = serve(1, &pools);
+    let mut ws = subscribe(port, "w2");
+
+    for _ in 0..2 {
+        let m = next(&mut ws);
+        assert_eq!(m["ID"], "w2");
+        assert_eq!(m["Active"], "yes");
+        assert_eq!(m["Hitcount"], "7");
+        assert_eq!(m["Launchtime"], "1622578053.906691");
+        assert_eq!(m["Remaining"], "");
+        assert!(m["Type"].is_null());
+    }
+}
+
+#[test]
+fn schema_one_as_go_sends_it() {
+    // --compat go: the keys in order and the HTML escaped
+    let pools = pools().withformat(HelperFormat::Go);
+    poll("w3", &[("active", "yes"), ("hitcount", "7"), ("notes", "<b>3 works</b>")], &pools);
+    let port = serve(1, &pools);
+    let mut ws = subscribe(port, "w3");
+
+    let m = ws.read_message().unwrap();
+    assert_eq!(m.to_text().unwrap(), concat!(r#"{"Active":"yes","Hitcount":"7","ID":"w3","Launchtime":"","#,
+        r#""Notes":"\u003cb\u003e3 works\u003c/b\u003e","Poolofwork":"","Portnumber":"","Remaining":"","Statusmessage":""}"#));
+}
+
+#[test]
+fn unknown_paths() {
+    let pools = pools();
+    let port = serve(2, &pools);
+    let (status, _) = get_http(port, "/nowhere");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn redis_tokens_work_once() {
+    let pools = pools();
+    set("t1_wstoken", "1", &pools);
+    let port = serve_gated(2, gate("redis", "", &pools), &pools);
+    assert!(admitted(port, "token=t1"));
+    assert_eq!(get("t1_wstoken", &pools), None);
+    assert!(!admitted(port, "token=t1"));
+
+    // nor can handshakes that arrive together share one
+    set("t2_wstoken", "1", &pools);
+    let tries: Vec<thread::JoinHandle<bool>> = (0..8).map(|_| thread::spawn(move || admitted(port, "token=t2"))).collect();
+    let got_in = tries.into_iter().map(|t| t.join().unwrap()).filter(|ok| *ok).count();
+    assert_eq!(got_in, 1);
+
+    // /status takes the token too, but leaves it for the socket
+    set("t3_wstoken", "1", &pools);
+    assert_eq!(get_http(port, "/status?token=t3").0, "HTTP/1.1 200 OK");
+    assert_eq!(get_http(port, "/status?token=t3").0, "HTTP/1.1 200 OK");
+    assert!(admitted(port, "token=t3"));
+    assert_eq!(get_http(port, "/status?token=t3").0, "HTTP/1.1 403 Forbidden");
+}
+
+#[test]
+fn secrets_are_percent_decoded() {
+    let pools = pools();
+    let port = serve_gated(2, gate("secret", "a+b&c%d", &pools), &pools);
+    assert!(admitted(port, "token=a%2Bb%26c%25d"));
+    assert!(!admitted(port, "token=a+b&c%d"));
+    assert!(!admitted(port, "token="));
+}
+
+fn refused(schema: i32, pools: DbPools) -> HelperError {
+    // websocket() should not even start listening
+    let gate = gate("none", "", &pools);
+    let settings = WsSettings {
+        failthreshold: 1,
+        ip: "127.0.0.1".to_string(),
+        port: "0".to_string(),
+        save: 0,
+        schema,
+        hitstostream: 0,
+        pools,
+        tls: None,
+    };
+    websocket(settings, gate).unwrap_err()
+}
+
+#[test]
+fn bad_settings() {
+    let e = refused(3, pools());
+    assert_eq!((e.kind(), e.exitcode()), ("unsupported", 6));
+    // the Go helper has no schema 2
+    let e = refused(2, pools().withformat(HelperFormat::Go));
+    assert_eq!((e.kind(), e.exitcode()), ("config", 2));
+}